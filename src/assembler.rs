@@ -0,0 +1,684 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::instruction::Instruction;
+
+const ORIGIN: u16 = 0x200;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+struct Line {
+    file: String,
+    number: usize,
+    text: String,
+}
+
+impl Line {
+    fn error<S: Into<String>>(&self, message: S) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.number,
+            message: message.into(),
+        }
+    }
+}
+
+enum Statement {
+    Instruction { mnemonic: String, operands: Vec<String> },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+struct Item {
+    line: Line,
+    addr: u16,
+    statement: Statement,
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut lines = Vec::new();
+    expand("<input>", source, Path::new("."), &mut Vec::new(), &mut lines)?;
+
+    assemble_lines(lines)
+}
+
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, AsmError> {
+    let mut lines = Vec::new();
+    include(path.as_ref(), None, &mut Vec::new(), &mut lines)?;
+
+    assemble_lines(lines)
+}
+
+fn include(path: &Path, from: Option<&Line>, stack: &mut Vec<PathBuf>, out: &mut Vec<Line>) -> Result<(), AsmError> {
+    let name = path.display().to_string();
+    let error = |message: String| match from {
+        Some(line) => line.error(message),
+        None => AsmError { file: name.clone(), line: 0, message },
+    };
+
+    let canonical = path.canonicalize().map_err(|err| error(format!("cannot open '{}': {}", name, err)))?;
+    if stack.contains(&canonical) {
+        return Err(error(format!("'{}' includes itself", name)));
+    }
+    let source = fs::read_to_string(path).map_err(|err| error(format!("cannot read '{}': {}", name, err)))?;
+
+    stack.push(canonical);
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    expand(&name, &source, dir, stack, out)?;
+    stack.pop();
+
+    Ok(())
+}
+
+// Splits a source file into lines with comments removed, recursively
+// replacing `include "file"` lines with the contents of that file.
+fn expand(file: &str, source: &str, dir: &Path, stack: &mut Vec<PathBuf>, out: &mut Vec<Line>) -> Result<(), AsmError> {
+    for (i, text) in source.lines().enumerate() {
+        let line = Line {
+            file: file.to_string(),
+            number: i + 1,
+            text: strip_comment(text).trim().to_string(),
+        };
+
+        let is_include = line.text.get(..7).is_some_and(|word| word.eq_ignore_ascii_case("include"))
+            && line.text[7..].starts_with(char::is_whitespace);
+        if !is_include {
+            out.push(line);
+            continue;
+        }
+
+        let target = match parse_string(line.text[7..].trim()) {
+            Some(target) => target,
+            None => return Err(line.error("include expects a quoted file name")),
+        };
+        include(&dir.join(target), Some(&line), stack, out)?;
+    }
+
+    Ok(())
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &text[..i],
+            _ => {}
+        }
+    }
+
+    text
+}
+
+fn parse_string(text: &str) -> Option<&str> {
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        Some(&text[1..text.len() - 1])
+    } else {
+        None
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.'
+}
+
+fn is_ident(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if is_ident_start(c) => chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.'),
+        _ => false,
+    }
+}
+
+fn split_operands(text: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut in_string = false;
+
+    for c in text.chars() {
+        match c {
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 0 => {
+                res.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+
+    if !current.trim().is_empty() || !res.is_empty() {
+        res.push(current.trim().to_string());
+    }
+
+    res
+}
+
+fn assemble_lines(lines: Vec<Line>) -> Result<Vec<u8>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut items = Vec::new();
+    let mut addr = u32::from(ORIGIN);
+
+    // first pass: assign addresses to labels and statements
+    for line in lines {
+        let mut rest = line.text.as_str();
+
+        // disassembler output is prefixed with "[0x0200]: "
+        if rest.starts_with('[') {
+            if let Some(end) = rest.find("]:") {
+                rest = rest[end + 2..].trim_start();
+            }
+        }
+
+        while let Some(colon) = rest.find(':') {
+            let label = rest[..colon].trim();
+            if !is_ident(label) {
+                break;
+            }
+            if labels.insert(label.to_string(), addr).is_some() {
+                return Err(line.error(format!("label '{}' is defined more than once", label)));
+            }
+            rest = rest[colon + 1..].trim_start();
+        }
+
+        if rest.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = match rest.find(char::is_whitespace) {
+            Some(split) => (rest[..split].to_ascii_uppercase(), split_operands(&rest[split..])),
+            None => (rest.to_ascii_uppercase(), Vec::new()),
+        };
+
+        let (statement, size) = match mnemonic.as_str() {
+            "DB" => {
+                let mut size = 0;
+                for operand in operands.iter() {
+                    size += parse_string(operand).map_or(1, str::len);
+                }
+                (Statement::Bytes(operands), size)
+            }
+            "DW" => {
+                let size = 2 * operands.len();
+                (Statement::Words(operands), size)
+            }
+            _ => {
+                let size = if is_long_load(&mnemonic, &operands) { 4 } else { 2 };
+                (Statement::Instruction { mnemonic, operands }, size)
+            }
+        };
+
+        if statement_is_empty(&statement) {
+            return Err(line.error("data directive needs at least one value"));
+        }
+
+        let item_addr = addr;
+        addr += size as u32;
+        if addr > 0x10000 {
+            return Err(line.error("program does not fit in memory"));
+        }
+
+        items.push(Item { line, addr: item_addr as u16, statement });
+    }
+
+    // second pass: evaluate operands and emit bytes
+    let mut res = Vec::new();
+    for item in items.iter() {
+        let line = &item.line;
+        debug_assert_eq!(usize::from(item.addr), usize::from(ORIGIN) + res.len());
+
+        match &item.statement {
+            Statement::Bytes(values) => {
+                for value in values {
+                    match parse_string(value) {
+                        Some(string) => res.extend_from_slice(string.as_bytes()),
+                        None => res.push(eval_range(line, value, &labels, -0x80, 0xFF)? as u8),
+                    }
+                }
+            }
+            Statement::Words(values) => {
+                for value in values {
+                    let word = eval_range(line, value, &labels, -0x8000, 0xFFFF)? as u16;
+                    res.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+            Statement::Instruction { mnemonic, operands } => {
+                let instruction = encode(line, mnemonic, operands, &labels)?;
+                res.extend_from_slice(&instruction.encode().to_be_bytes());
+
+                if instruction == Instruction::LdILong {
+                    if let Operand::Long(target) = parse_operand(&operands[1]) {
+                        let target = eval_range(line, target, &labels, 0, 0xFFFF)? as u16;
+                        res.extend_from_slice(&target.to_be_bytes());
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+// F000 NNNN is the only instruction followed by an extra operand word
+fn is_long_load(mnemonic: &str, operands: &[String]) -> bool {
+    mnemonic == "LD" && operands.len() == 2 && matches!(parse_operand(&operands[1]), Operand::Long(_))
+}
+
+fn statement_is_empty(statement: &Statement) -> bool {
+    match statement {
+        Statement::Bytes(values) | Statement::Words(values) => values.is_empty(),
+        Statement::Instruction { .. } => false,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Operand<'a> {
+    V(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Audio,
+    Pitch,
+    Long(&'a str),
+    Expr(&'a str),
+}
+
+fn parse_operand(text: &str) -> Operand<'_> {
+    let upper = text.to_ascii_uppercase();
+    match upper.as_str() {
+        "I" => return Operand::I,
+        "[I]" => return Operand::IndirectI,
+        "DT" => return Operand::Dt,
+        "ST" => return Operand::St,
+        "K" => return Operand::K,
+        "F" => return Operand::F,
+        "HF" => return Operand::Hf,
+        "B" => return Operand::B,
+        "R" => return Operand::R,
+        "AUDIO" => return Operand::Audio,
+        "PITCH" => return Operand::Pitch,
+        _ => {}
+    }
+
+    if upper.starts_with("LONG") && text[4..].starts_with(char::is_whitespace) {
+        return Operand::Long(text[4..].trim());
+    }
+
+    let bytes = upper.as_bytes();
+    if bytes.len() == 2 && bytes[0] == b'V' {
+        if let Some(reg) = (bytes[1] as char).to_digit(16) {
+            return Operand::V(reg as u8);
+        }
+    }
+
+    Operand::Expr(text)
+}
+
+fn encode(line: &Line, mnemonic: &str, operands: &[String], labels: &HashMap<String, u32>) -> Result<Instruction, AsmError> {
+    let parsed: Vec<Operand> = operands.iter().map(|operand| parse_operand(operand)).collect();
+
+    let addr = |expr: &str| eval_range(line, expr, labels, 0, 0xFFF).map(|value| value as u16);
+    let byte = |expr: &str| eval_range(line, expr, labels, -0x80, 0xFF).map(|value| value as u8);
+    let nibble = |expr: &str| eval_range(line, expr, labels, 0, 0xF).map(|value| value as u8);
+
+    use Operand::*;
+    let res = match (mnemonic, parsed.as_slice()) {
+        ("CLS", []) => Instruction::Cls,
+        ("RET", []) => Instruction::Ret,
+        ("SCD", [Expr(n)]) => Instruction::ScdNibble(nibble(n)?),
+        ("SCR", []) => Instruction::Scr,
+        ("SCL", []) => Instruction::Scl,
+        ("EXIT", []) => Instruction::Exit,
+        ("LOW", []) => Instruction::Low,
+        ("HIGH", []) => Instruction::High,
+        ("JP", [Expr(target)]) => Instruction::JpAddr(addr(target)?),
+        ("JP", [V(0), Expr(target)]) => Instruction::JpV0Addr(addr(target)?),
+        ("CALL", [Expr(target)]) => Instruction::CallAddr(addr(target)?),
+        ("SE", [V(x), V(y)]) => Instruction::SeVxVy(*x, *y),
+        ("SE", [V(x), Expr(value)]) => Instruction::SeVxByte(*x, byte(value)?),
+        ("SNE", [V(x), V(y)]) => Instruction::SneVxVy(*x, *y),
+        ("SNE", [V(x), Expr(value)]) => Instruction::SneVxByte(*x, byte(value)?),
+        ("LD", [V(x), V(y)]) => Instruction::LdVxVy(*x, *y),
+        ("LD", [V(x), Expr(value)]) => Instruction::LdVxByte(*x, byte(value)?),
+        ("LD", [I, Expr(target)]) => Instruction::LdIAddr(addr(target)?),
+        ("LD", [V(x), Dt]) => Instruction::LdVxDt(*x),
+        ("LD", [V(x), K]) => Instruction::LdVxK(*x),
+        ("LD", [Dt, V(x)]) => Instruction::LdDtVx(*x),
+        ("LD", [St, V(x)]) => Instruction::LdStVx(*x),
+        ("LD", [F, V(x)]) => Instruction::LdFVx(*x),
+        ("LD", [B, V(x)]) => Instruction::LdBVx(*x),
+        ("LD", [IndirectI, V(x)]) => Instruction::LdIVx(*x),
+        ("LD", [V(x), IndirectI]) => Instruction::LdVxI(*x),
+        ("LD", [Hf, V(x)]) => Instruction::LdHfVx(*x),
+        ("LD", [R, V(x)]) => Instruction::LdRVx(*x),
+        ("LD", [V(x), R]) => Instruction::LdVxR(*x),
+        ("LD", [I, Long(_)]) => Instruction::LdILong,
+        ("LD", [Audio, IndirectI]) => Instruction::LdAudioI,
+        ("LD", [Pitch, V(x)]) => Instruction::LdPitchVx(*x),
+        ("SAVE", [V(x), V(y)]) => Instruction::SaveVxVy(*x, *y),
+        ("LOAD", [V(x), V(y)]) => Instruction::LoadVxVy(*x, *y),
+        ("PLANE", [Expr(n)]) => Instruction::PlaneN(eval_range(line, n, labels, 0, 3)? as u8),
+        ("ADD", [V(x), V(y)]) => Instruction::AddVxVy(*x, *y),
+        ("ADD", [V(x), Expr(value)]) => Instruction::AddVxByte(*x, byte(value)?),
+        ("ADD", [I, V(x)]) => Instruction::AddIVx(*x),
+        ("OR", [V(x), V(y)]) => Instruction::OrVxVy(*x, *y),
+        ("AND", [V(x), V(y)]) => Instruction::AndVxVy(*x, *y),
+        ("XOR", [V(x), V(y)]) => Instruction::XorVxVy(*x, *y),
+        ("SUB", [V(x), V(y)]) => Instruction::SubVxVy(*x, *y),
+        ("SUBN", [V(x), V(y)]) => Instruction::SubnVxVy(*x, *y),
+        // shifting Vx into itself does the same under either shift quirk
+        ("SHR", [V(x)]) => Instruction::ShrVxVy(*x, *x),
+        ("SHR", [V(x), V(y)]) => Instruction::ShrVxVy(*x, *y),
+        ("SHL", [V(x)]) => Instruction::ShlVxVy(*x, *x),
+        ("SHL", [V(x), V(y)]) => Instruction::ShlVxVy(*x, *y),
+        ("RND", [V(x), Expr(value)]) => Instruction::RndVxByte(*x, byte(value)?),
+        ("DRW", [V(x), V(y), Expr(n)]) => Instruction::DrwVxVyNibble(*x, *y, nibble(n)?),
+        ("SKP", [V(x)]) => Instruction::SkpVx(*x),
+        ("SKNP", [V(x)]) => Instruction::SknpVx(*x),
+        _ => {
+            let known = ["CLS", "RET", "SCD", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "SAVE", "LOAD", "PLANE"];
+            if known.contains(&mnemonic) {
+                return Err(line.error(format!("invalid operands for {}: '{}'", mnemonic, operands.join(", "))));
+            } else {
+                return Err(line.error(format!("unknown mnemonic '{}'", mnemonic)));
+            }
+        }
+    };
+
+    Ok(res)
+}
+
+fn eval_range(line: &Line, expr: &str, labels: &HashMap<String, u32>, min: i64, max: i64) -> Result<i64, AsmError> {
+    let value = eval(expr, labels).map_err(|message| line.error(message))?;
+
+    if value < min || value > max {
+        return Err(line.error(format!("value {} of '{}' is out of range", value, expr)));
+    }
+
+    Ok(value)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    const OPS: [&str; 11] = ["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~"];
+
+    let mut res = Vec::new();
+    let mut rest = expr.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            let literal = &rest[..end];
+            let lower = literal.to_ascii_lowercase();
+            let value = if let Some(hex) = lower.strip_prefix("0x") {
+                i64::from_str_radix(hex, 16)
+            } else if let Some(bin) = lower.strip_prefix("0b") {
+                i64::from_str_radix(bin, 2)
+            } else {
+                lower.parse()
+            };
+            res.push(Token::Num(value.map_err(|_| format!("invalid number '{}'", literal))?));
+            rest = &rest[end..];
+        } else if is_ident_start(c) {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+            res.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c == '(' {
+            res.push(Token::LParen);
+            rest = &rest[1..];
+        } else if c == ')' {
+            res.push(Token::RParen);
+            rest = &rest[1..];
+        } else if let Some(op) = OPS.iter().find(|op| rest.starts_with(*op)) {
+            res.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(format!("unexpected character '{}' in expression", c));
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(res)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    labels: &'a HashMap<String, u32>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek_op(&self, ops: &[&str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(op) => Some(op),
+            _ => None,
+        }
+    }
+
+    // binary operators, lowest precedence first
+    const LEVELS: [&'static [&'static str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        if level == Parser::LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op(Parser::LEVELS[level]) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = match op {
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "<<" | ">>" if !(0..64).contains(&rhs) => return Err(format!("invalid shift amount {}", rhs)),
+                "<<" => lhs << rhs,
+                ">>" => lhs >> rhs,
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                "/" | "%" if rhs == 0 => return Err("division by zero".to_string()),
+                // the only other way to fail is the most negative number divided by -1
+                "/" => lhs.checked_div(rhs).ok_or("division overflow")?,
+                "%" => lhs.checked_rem(rhs).ok_or("division overflow")?,
+                _ => unreachable!(),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if let Some(op) = self.peek_op(&["-", "~", "+"]) {
+            self.pos += 1;
+            let value = self.unary()?;
+            return Ok(match op {
+                "-" => value.wrapping_neg(),
+                "~" => !value,
+                _ => value,
+            });
+        }
+
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Num(value)) => Ok(value),
+            Some(Token::Ident(name)) => match self.labels.get(&name) {
+                Some(&addr) => Ok(i64::from(addr)),
+                None => Err(format!("undefined label '{}'", name)),
+            },
+            Some(Token::LParen) => {
+                let value = self.binary(0)?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::RParen) => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            _ => Err("expected a value".to_string()),
+        }
+    }
+}
+
+fn eval(expr: &str, labels: &HashMap<String, u32>) -> Result<i64, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expr)?,
+        pos: 0,
+        labels,
+    };
+
+    if parser.tokens.is_empty() {
+        return Err("missing operand".to_string());
+    }
+
+    let value = parser.binary(0)?;
+    if parser.pos != parser.tokens.len() {
+        return Err(format!("unexpected trailing input in '{}'", expr));
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::instruction::disassemble;
+
+    #[test]
+    fn test_instructions() {
+        let source = "
+            CLS
+            ld v1, 0x05     ; lower case works too
+            LD I, 0x300
+            DRW V0, V1, 0x5
+            SHR V2
+            JP V0, 0x0200
+            LD [I], VA
+            HIGH
+            SCD 3
+            LD HF, V2
+            LD V7, R
+            PLANE 3
+            LD I, LONG 0x1234
+            SAVE V1, V4
+            LD AUDIO, [I]
+            LD PITCH, V0
+        ";
+
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x00, 0xE0, 0x61, 0x05, 0xA3, 0x00, 0xD0, 0x15, 0x82, 0x26, 0xB2, 0x00, 0xFA, 0x55, 0x00, 0xFF, 0x00, 0xC3, 0xF2, 0x30, 0xF7, 0x85,
+                 0xF3, 0x01, 0xF0, 0x00, 0x12, 0x34, 0x51, 0x42, 0xF0, 0x02, 0xF0, 0x3A],
+        );
+    }
+
+    #[test]
+    fn test_one_operand_shifts() {
+        assert_eq!(assemble("SHR V2").unwrap(), vec![0x82, 0x26]);
+        assert_eq!(assemble("SHL VA").unwrap(), vec![0x8A, 0xAE]);
+    }
+
+    #[test]
+    fn test_labels_and_expressions() {
+        let source = "
+            start:
+                JP main
+            sprite: DB 0b11110000, 0x90, \"AB\"
+            table:  DW sprite, -1
+            main:   LD I, sprite + (table - sprite) / 2
+                    ADD V0, (1 << 4) | 3
+                    JP start
+        ";
+
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x12, 0x0A, 0xF0, 0x90, 0x41, 0x42, 0x02, 0x02, 0xFF, 0xFF, 0xA2, 0x04, 0x70, 0x13, 0x12, 0x00],
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = assemble("CLS\n  FOO V1").unwrap_err();
+        assert_eq!(err.to_string(), "<input>:2: unknown mnemonic 'FOO'");
+
+        assert_eq!(assemble("JP nowhere").unwrap_err().message, "undefined label 'nowhere'");
+        assert_eq!(assemble("LD V0, 256").unwrap_err().message, "value 256 of '256' is out of range");
+        assert_eq!(assemble("DRW V0, V1").unwrap_err().message, "invalid operands for DRW: 'V0, V1'");
+        assert_eq!(assemble("a:\na: CLS").unwrap_err().message, "label 'a' is defined more than once");
+        assert_eq!(assemble("LD V0, (1").unwrap_err().message, "missing ')'");
+        assert_eq!(assemble("DW 1 / 0").unwrap_err().message, "division by zero");
+        assert_eq!(assemble("DW (-0x7FFFFFFFFFFFFFFF - 1) / -1").unwrap_err().message, "division overflow");
+        assert_eq!(assemble("DW (-0x7FFFFFFFFFFFFFFF - 1) % -1").unwrap_err().message, "division overflow");
+        assert!(assemble("include \"does-not-exist.asm\"").is_err());
+    }
+
+    #[test]
+    fn test_non_ascii_source() {
+        // the first seven bytes are checked for "include", which mustn't split a character
+        assert_eq!(assemble("ÄÄÄÄ: CLS").unwrap_err().message, "unknown mnemonic 'ÄÄÄÄ:'");
+        // the seventh byte falls inside the ë
+        assert!(assemble("includë \"a.asm\"").is_err());
+        assert_eq!(assemble("CLS ; größer").unwrap(), vec![0x00, 0xE0]);
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("chip8-asm-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.asm"), "JP font\ninclude \"font.asm\"\n").unwrap();
+        fs::write(dir.join("font.asm"), "font: DB 0xF0 ; zero\n").unwrap();
+        fs::write(dir.join("loop.asm"), "include \"loop.asm\"\n").unwrap();
+
+        assert_eq!(assemble_file(dir.join("main.asm")).unwrap(), vec![0x12, 0x02, 0xF0]);
+        assert!(assemble_file(dir.join("loop.asm")).unwrap_err().message.contains("includes itself"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_disassembly() {
+        for rom in &["roms/maze.ch8", "roms/test_opcode.ch8", "roms/invaders.ch8"] {
+            let bytes = fs::read(rom).unwrap();
+            let source = disassemble(&bytes, 0x200).join("\n");
+
+            assert_eq!(assemble(&source).unwrap(), bytes, "{}", rom);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use std::time::Instant;
+
+pub trait Audio {
+    fn set_beeping(&mut self, beeping: bool);
+
+    // XO-CHIP 128 bit sample buffer, played back at pattern_rate(pitch) bits
+    // per second. None goes back to the plain buzzer tone.
+    fn set_pattern(&mut self, _pattern: Option<[u8; 16]>, _pitch: u8) {}
+}
+
+impl<A: Audio + ?Sized> Audio for Box<A> {
+    fn set_beeping(&mut self, beeping: bool) {
+        (**self).set_beeping(beeping);
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        (**self).set_pattern(pattern, pitch);
+    }
+}
+
+pub fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((f32::from(pitch) - 64.0) / 48.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Sawtooth,
+}
+
+impl FromStr for Waveform {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            "sawtooth" | "saw" => Ok(Waveform::Sawtooth),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Waveform::Square => write!(f, "square"),
+            Waveform::Sine => write!(f, "sine"),
+            Waveform::Triangle => write!(f, "triangle"),
+            Waveform::Sawtooth => write!(f, "sawtooth"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub frequency: f32,
+    pub waveform: Waveform,
+    pub volume: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+        }
+    }
+}
+
+pub struct ToneGenerator {
+    tone: Tone,
+    sample_rate: f32,
+    phase: f32, // position within the current period, in [0, 1)
+    phase_inc: f32,
+    pattern: Option<[u8; 16]>,
+}
+
+impl ToneGenerator {
+    pub fn new(tone: Tone, sample_rate: u32) -> Self {
+        assert!(sample_rate > 0);
+
+        ToneGenerator {
+            tone,
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+            phase_inc: tone.frequency / sample_rate as f32,
+            pattern: None,
+        }
+    }
+
+    // Replaces the configured tone with a looping XO-CHIP pattern, or brings
+    // the tone back if pattern is None
+    pub fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        self.pattern = pattern;
+        self.phase_inc = match pattern {
+            Some(_) => pattern_rate(pitch) / 128.0 / self.sample_rate,
+            None => self.tone.frequency / self.sample_rate,
+        };
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let sample = if let Some(pattern) = self.pattern {
+            let bit = (self.phase * 128.0) as usize % 128;
+            if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 }
+        } else {
+            match self.tone.waveform {
+                Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+                Waveform::Sine => (2.0 * PI * self.phase).sin(),
+                Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+                Waveform::Sawtooth => 2.0 * self.phase - 1.0,
+            }
+        };
+
+        self.phase = (self.phase + self.phase_inc) % 1.0;
+
+        sample * self.tone.volume
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NullAudio;
+
+impl Audio for NullAudio {
+    fn set_beeping(&mut self, _beeping: bool) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BeepTransition {
+    pub tick: u64,
+    pub elapsed: Duration,
+    pub beeping: bool,
+}
+
+#[derive(Clone)]
+pub struct RecordingAudio {
+    start: Instant,
+    ticks: u64,
+    beeping: bool,
+    transitions: Vec<BeepTransition>,
+    pattern: Option<([u8; 16], u8)>,
+}
+
+impl RecordingAudio {
+    pub fn new() -> Self {
+        RecordingAudio {
+            start: Instant::now(),
+            ticks: 0,
+            beeping: false,
+            transitions: Vec::new(),
+            pattern: None,
+        }
+    }
+
+    pub fn is_beeping(&self) -> bool {
+        self.beeping
+    }
+
+    pub fn transitions(&self) -> &[BeepTransition] {
+        &self.transitions
+    }
+
+    pub fn pattern(&self) -> Option<([u8; 16], u8)> {
+        self.pattern
+    }
+}
+
+impl Default for RecordingAudio {
+    fn default() -> Self {
+        RecordingAudio::new()
+    }
+}
+
+impl Audio for RecordingAudio {
+    fn set_beeping(&mut self, beeping: bool) {
+        if beeping != self.beeping {
+            self.transitions.push(BeepTransition {
+                tick: self.ticks,
+                elapsed: self.start.elapsed(),
+                beeping,
+            });
+            self.beeping = beeping;
+        }
+
+        self.ticks += 1;
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        self.pattern = pattern.map(|pattern| (pattern, pitch));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_square_wave() {
+        let tone = Tone { frequency: 1.0, waveform: Waveform::Square, volume: 0.5 };
+        let mut gen = ToneGenerator::new(tone, 4);
+
+        let samples: Vec<f32> = (0..8).map(|_| gen.next_sample()).collect();
+        assert_eq!(samples, vec![0.5, 0.5, -0.5, -0.5, 0.5, 0.5, -0.5, -0.5]);
+    }
+
+    #[test]
+    fn test_waveforms_within_volume() {
+        for &waveform in &[Waveform::Square, Waveform::Sine, Waveform::Triangle, Waveform::Sawtooth] {
+            let tone = Tone { frequency: 440.0, waveform, volume: 0.3 };
+            let mut gen = ToneGenerator::new(tone, 44100);
+
+            for _ in 0..1000 {
+                assert!(gen.next_sample().abs() <= 0.3 + f32::EPSILON);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pattern_playback() {
+        let tone = Tone { frequency: 440.0, waveform: Waveform::Sine, volume: 0.5 };
+        let mut gen = ToneGenerator::new(tone, 4000);
+
+        // pitch 64 plays 4000 bits per second, so one bit per sample
+        let mut pattern = [0; 16];
+        pattern[0] = 0b1010_0000;
+        gen.set_pattern(Some(pattern), 64);
+
+        let samples: Vec<f32> = (0..4).map(|_| gen.next_sample()).collect();
+        assert_eq!(samples, vec![0.5, -0.5, 0.5, -0.5]);
+        assert_eq!(pattern_rate(64), 4000.0);
+        assert_eq!(pattern_rate(112), 8000.0);
+    }
+
+    #[test]
+    fn test_recording_transitions() {
+        let mut audio = RecordingAudio::new();
+
+        audio.set_beeping(false);
+        audio.set_beeping(true);
+        audio.set_beeping(true);
+        audio.set_beeping(false);
+
+        let ticks: Vec<(u64, bool)> = audio.transitions().iter().map(|t| (t.tick, t.beeping)).collect();
+        assert_eq!(ticks, vec![(1, true), (3, false)]);
+        assert!(!audio.is_beeping());
+    }
+}
@@ -0,0 +1,61 @@
+use std::path::Path;
+use std::process;
+
+use emulator::assembler;
+
+const USAGE: &str = "\
+Usage: assembler <SOURCE> [-o <OUTPUT>]
+
+Assembles CHIP-8 mnemonics into a .ch8 ROM. The output defaults to the
+source file name with a .ch8 extension.";
+
+fn main() {
+    let mut source = None;
+    let mut output = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "-o" | "--output" => match args.next() {
+                Some(path) => output = Some(path),
+                None => {
+                    eprintln!("error: option '{}' needs a value\n\n{}", arg, USAGE);
+                    process::exit(2);
+                }
+            },
+            _ if source.is_none() => source = Some(arg),
+            _ => {
+                eprintln!("error: unexpected argument '{}'\n\n{}", arg, USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    let source = match source {
+        Some(source) => source,
+        None => {
+            eprintln!("error: no source file given\n\n{}", USAGE);
+            process::exit(2);
+        }
+    };
+    let output = output.unwrap_or_else(|| Path::new(&source).with_extension("ch8").display().to_string());
+
+    let rom = match assembler::assemble_file(&source) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    };
+
+    if let Err(err) = std::fs::write(&output, &rom) {
+        eprintln!("error: failed to write '{}': {}", output, err);
+        process::exit(1);
+    }
+
+    println!("Wrote {} bytes to {}", rom.len(), output);
+}
@@ -0,0 +1,298 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use emulator::chip8::Chip8;
+use emulator::cpu::StepOutcome;
+use emulator::quirks::QuirkProfile;
+use emulator::rng::SeededRng;
+use emulator::screenshot::Screenshot;
+
+const USAGE: &str = "\
+Usage: golden [OPTIONS] <ROM|DIR>...
+
+Runs ROMs headless and compares the final screen with a golden screenshot
+named after the ROM, e.g. roms/maze.ch8 is checked against <GOLDEN>/maze.txt.
+Directories are searched for .ch8 files.
+
+Options:
+    -g, --golden <DIR>     Directory of golden files (default: tests/golden)
+    -f, --frames <N>       Emulated frames to run each ROM for (default: 120)
+    -q, --quirks <NAME>    Quirk profile: vip, chip48, schip or xochip (default: vip)
+        --seed <N>         Seed for the RND instruction (default: 0)
+        --pbm              Use PBM images instead of ASCII art for new golden files
+    -u, --update           Write the golden files instead of comparing against them
+    -h, --help             Print this message and exit
+
+A <GOLDEN>/<NAME>.script file next to the golden file overrides the options
+for that ROM and presses keys. One command per line, '#' starts a comment:
+    frames <N>
+    quirks <NAME>
+    seed <N>
+    <FRAME> down <KEY>     Press a key (hex) before running frame FRAME
+    <FRAME> up <KEY>       Release it again";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Script {
+    frames: usize,
+    quirks: QuirkProfile,
+    seed: u64,
+    // (frame, key, pressed), in frame order
+    keys: Vec<(usize, u8, bool)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct ScriptError {
+    line: usize,
+    reason: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl Script {
+    fn parse(text: &str, defaults: &Script) -> Result<Script, ScriptError> {
+        let mut script = defaults.clone();
+
+        for (i, line) in text.lines().enumerate() {
+            let error = |reason: &str| ScriptError { line: i + 1, reason: reason.to_string() };
+
+            let words: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["frames", n] => script.frames = n.parse().map_err(|_| error("invalid frame count"))?,
+                ["quirks", name] => script.quirks = name.parse().map_err(|_| error("unknown quirk profile"))?,
+                ["seed", n] => script.seed = n.parse().map_err(|_| error("invalid seed"))?,
+                [frame, action @ "down", key] | [frame, action @ "up", key] => {
+                    let frame: usize = frame.parse().map_err(|_| error("invalid frame number"))?;
+                    let key = u8::from_str_radix(key, 16).ok().filter(|&key| key < 16).ok_or_else(|| error("keys are 0 to F"))?;
+                    if script.keys.last().is_some_and(|&(last, _, _)| frame < last) {
+                        return Err(error("key presses must be in frame order"));
+                    }
+                    script.keys.push((frame, key, *action == "down"));
+                }
+                _ => return Err(error("unknown command")),
+            }
+        }
+
+        Ok(script)
+    }
+}
+
+// Runs a ROM for script.frames frames and returns the final screen
+fn run(rom: &[u8], script: &Script) -> Result<Screenshot, String> {
+    let mut chip8 = Chip8::new(script.quirks);
+    chip8.cpu_mut().set_rng(Box::new(SeededRng::new(script.seed)));
+    chip8.load_rom(rom).map_err(|err| err.to_string())?;
+
+    let mut keys = script.keys.iter().peekable();
+    for frame in 0..script.frames {
+        while let Some(&(_, key, pressed)) = keys.next_if(|&&(at, _, _)| at <= frame) {
+            chip8.set_key(key, pressed);
+        }
+
+        match chip8.run_frame() {
+            Ok(StepOutcome::Exited) => break,
+            Ok(_) => {}
+            Err(err) => return Err(format!("crashed in frame {}: {}", frame, err)),
+        }
+    }
+
+    Ok(Screenshot::capture(chip8.framebuffer()))
+}
+
+struct Options {
+    paths: Vec<String>,
+    golden: PathBuf,
+    defaults: Script,
+    pbm: bool,
+    update: bool,
+}
+
+fn is_pbm(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "pbm")
+}
+
+// Checks one ROM, returning a description of the failure if it didn't pass
+fn check(rom_path: &Path, options: &Options) -> Result<String, String> {
+    let name = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let script_path = options.golden.join(format!("{}.script", name));
+    let script = match fs::read_to_string(&script_path) {
+        Ok(text) => Script::parse(&text, &options.defaults).map_err(|err| format!("{}: {}", script_path.display(), err))?,
+        Err(_) => options.defaults.clone(),
+    };
+
+    let rom = fs::read(rom_path).map_err(|err| format!("failed to read ROM: {}", err))?;
+    let screenshot = run(&rom, &script)?;
+
+    let ascii_path = options.golden.join(format!("{}.txt", name));
+    let pbm_path = options.golden.join(format!("{}.pbm", name));
+    let existing = [&ascii_path, &pbm_path].iter().find(|path| path.exists()).map(|path| path.to_path_buf());
+
+    if options.update {
+        let path = existing.unwrap_or(if options.pbm { pbm_path } else { ascii_path });
+        let contents = if is_pbm(&path) { screenshot.to_pbm() } else { screenshot.to_ascii() };
+        fs::write(&path, contents).map_err(|err| format!("failed to write {}: {}", path.display(), err))?;
+        return Ok(format!("wrote {}", path.display()));
+    }
+
+    let path = existing.ok_or_else(|| format!("no golden file {}, run with --update to create it", ascii_path.display()))?;
+    let text = fs::read_to_string(&path).map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let (expected, found) = if is_pbm(&path) {
+        (Screenshot::from_pbm(&text), screenshot.monochrome())
+    } else {
+        (Screenshot::from_ascii(&text), screenshot)
+    };
+    let expected = expected.map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    match found.diff(&expected) {
+        None => Ok("ok".to_string()),
+        Some(diff) => Err(format!("{} ({}):\n{}", diff, path.display(), found.diff_ascii(&expected))),
+    }
+}
+
+fn roms(paths: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut roms = Vec::new();
+
+    for path in paths.iter().map(PathBuf::from) {
+        if !path.is_dir() {
+            roms.push(path);
+            continue;
+        }
+
+        let entries = fs::read_dir(&path).map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        let mut found: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "ch8"))
+            .collect();
+        found.sort();
+        roms.extend(found);
+    }
+
+    Ok(roms)
+}
+
+fn usage_error(message: String) -> ! {
+    eprintln!("error: {}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        paths: Vec::new(),
+        golden: PathBuf::from("tests/golden"),
+        defaults: Script { frames: 120, quirks: QuirkProfile::Vip, seed: 0, keys: Vec::new() },
+        pbm: false,
+        update: false,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage_error(format!("option '{}' needs a value", arg)));
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "-g" | "--golden" => options.golden = PathBuf::from(value()),
+            "-f" | "--frames" => {
+                let frames = value();
+                options.defaults.frames = frames.parse().unwrap_or_else(|_| usage_error(format!("invalid frame count '{}'", frames)));
+            }
+            "-q" | "--quirks" => {
+                let name = value();
+                options.defaults.quirks = name.parse().unwrap_or_else(|_| usage_error(format!("unknown quirk profile '{}'", name)));
+            }
+            "--seed" => {
+                let seed = value();
+                options.defaults.seed = seed.parse().unwrap_or_else(|_| usage_error(format!("invalid seed '{}'", seed)));
+            }
+            "--pbm" => options.pbm = true,
+            "-u" | "--update" => options.update = true,
+            flag if flag.starts_with('-') && flag.len() > 1 => usage_error(format!("unknown option '{}'", flag)),
+            _ => options.paths.push(arg),
+        }
+    }
+
+    if options.paths.is_empty() {
+        usage_error("no ROM given".to_string());
+    }
+
+    options
+}
+
+fn main() {
+    let options = parse_args();
+
+    let roms = match roms(&options.paths) {
+        Ok(roms) => roms,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let mut failed = 0;
+    for rom in &roms {
+        match check(rom, &options) {
+            Ok(result) => println!("{}: {}", rom.display(), result),
+            Err(err) => {
+                println!("{}: FAILED {}", rom.display(), err);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{} passed, {} failed", roms.len() - failed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> Script {
+        Script { frames: 120, quirks: QuirkProfile::Vip, seed: 0, keys: Vec::new() }
+    }
+
+    #[test]
+    fn test_parse_script() {
+        let script = Script::parse("# start the game\nframes 300\nquirks schip\n\n10 down a\n12 up A # let go\n", &defaults()).unwrap();
+
+        assert_eq!(script.frames, 300);
+        assert_eq!(script.quirks, QuirkProfile::SuperChip);
+        assert_eq!(script.seed, 0);
+        assert_eq!(script.keys, vec![(10, 0xA, true), (12, 0xA, false)]);
+        assert_eq!(Script::parse("", &defaults()), Ok(defaults()));
+    }
+
+    #[test]
+    fn test_parse_script_errors() {
+        let error = |text| Script::parse(text, &defaults()).unwrap_err();
+
+        assert_eq!(error("frames many"), ScriptError { line: 1, reason: "invalid frame count".to_string() });
+        assert_eq!(error("\n5 down 10").line, 2);
+        assert_eq!(error("5 down 1\n4 up 1").reason, "key presses must be in frame order");
+        assert_eq!(error("jump").reason, "unknown command");
+    }
+
+    #[test]
+    fn test_run_presses_keys() {
+        // LD V0, K; LD F, V0; DRW V1, V1, 5; JP 0x206
+        let rom = [0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06];
+        let mut script = defaults();
+        script.frames = 5;
+        script.keys = vec![(2, 0x1, true), (3, 0x1, false)];
+
+        let screenshot = run(&rom, &script).unwrap();
+        // the "1" glyph is 0x20, 0x60, 0x20, 0x20, 0x70
+        assert_eq!(&screenshot.to_ascii()[..5], "..#..");
+    }
+}
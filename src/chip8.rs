@@ -0,0 +1,196 @@
+use crate::audio::{Audio, NullAudio};
+use crate::cpu::{Cpu, StepOutcome};
+use crate::display::{Display, HeadlessDisplay};
+use crate::error::EmuError;
+use crate::keypad::{Keypad, ScriptedKeypad};
+use crate::quirks::QuirkProfile;
+use crate::ram::Ram;
+
+// A complete machine, for embedding the emulator in other programs. By
+// default it is headless, with output read back after running rather than
+// pushed to peripherals, and keys set from code. Front ends plug in their own
+// display, keypad and audio with with_backends.
+#[derive(Clone)]
+pub struct Chip8<D = HeadlessDisplay, K = ScriptedKeypad, A = NullAudio> {
+    cpu: Cpu<D, K, A>,
+}
+
+impl Chip8 {
+    pub fn new(profile: QuirkProfile) -> Self {
+        Chip8::with_backends(profile, HeadlessDisplay::new(), ScriptedKeypad::new(), NullAudio)
+    }
+
+    // Takes effect right away rather than when the frame ends, since keys are
+    // set between frames anyway
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let keypad = self.cpu.keypad_mut();
+        if pressed {
+            keypad.press(key);
+        } else {
+            keypad.release(key);
+        }
+
+        self.cpu.poll_input().expect("the keypad never quits");
+    }
+}
+
+impl<D: Display, K: Keypad, A: Audio> Chip8<D, K, A> {
+    pub fn with_backends(profile: QuirkProfile, display: D, keypad: K, audio: A) -> Self {
+        let mut cpu = Cpu::new(Ram::with_size(profile.memory_size()), display, keypad, audio);
+        cpu.set_quirks(profile.quirks());
+
+        Chip8 { cpu }
+    }
+
+    // For anything not covered below, such as save states, the debugger or
+    // changing the clock
+    pub fn cpu(&self) -> &Cpu<D, K, A> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu<D, K, A> {
+        &mut self.cpu
+    }
+
+    pub fn into_cpu(self) -> Cpu<D, K, A> {
+        self.cpu
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), EmuError> {
+        self.cpu.load_rom(rom)
+    }
+
+    pub fn step(&mut self) -> Result<StepOutcome, EmuError> {
+        self.cpu.tick()
+    }
+
+    // Runs until the end of the current emulated frame or until the program
+    // exits. Returns the outcome of the last step, which is WaitingForKey if
+    // the frame ended in the middle of Fx0A.
+    pub fn run_frame(&mut self) -> Result<StepOutcome, EmuError> {
+        let outcome = self.run_frame_with(|cpu| cpu.tick().map(Some))?;
+
+        Ok(outcome.expect("ticking never stops a frame early"))
+    }
+
+    // Like run_frame, but runs each instruction with `step`, e.g. to trace it
+    // or check for breakpoints afterwards. `step` returning None stops the
+    // frame early, and so does this.
+    pub fn run_frame_with<F>(&mut self, mut step: F) -> Result<Option<StepOutcome>, EmuError>
+    where
+        F: FnMut(&mut Cpu<D, K, A>) -> Result<Option<StepOutcome>, EmuError>,
+    {
+        let frame = self.cpu.frames();
+        loop {
+            match step(&mut self.cpu)? {
+                None => return Ok(None),
+                Some(StepOutcome::Exited) => return Ok(Some(StepOutcome::Exited)),
+                Some(outcome) if self.cpu.frames() != frame => return Ok(Some(outcome)),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn framebuffer(&self) -> &D {
+        self.cpu.display()
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.cpu.st() > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::Display;
+
+    fn program(opcodes: &[u16]) -> Vec<u8> {
+        opcodes.iter().flat_map(|opcode| opcode.to_be_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut chip8 = Chip8::new(QuirkProfile::Vip);
+        // LD I, 0x000; DRW V0, V0, 5; LD V0, 0x02; LD ST, V0; JP 0x208
+        chip8.load_rom(&program(&[0xA000, 0xD005, 0x6002, 0xF018, 0x1208])).unwrap();
+
+        assert!(matches!(chip8.run_frame(), Ok(StepOutcome::Executed(_))));
+        assert_eq!((chip8.cpu().frames(), chip8.cpu().cycles()), (1, 8));
+        assert_eq!(chip8.framebuffer().vram_get(0, 0), 1);
+        assert!(chip8.sound_active());
+
+        chip8.run_frame().unwrap();
+        assert!(!chip8.sound_active());
+    }
+
+    #[test]
+    fn test_run_frame_with() {
+        let mut chip8 = Chip8::new(QuirkProfile::Vip);
+        // ADD V0, 0x01; JP 0x200
+        chip8.load_rom(&program(&[0x7001, 0x1200])).unwrap();
+
+        let mut steps = 0;
+        assert!(matches!(chip8.run_frame_with(|cpu| { steps += 1; cpu.tick().map(Some) }), Ok(Some(StepOutcome::Executed(_)))));
+        assert_eq!(steps, 8);
+
+        // stop once V0 reaches 6, partway through the second frame
+        let outcome = chip8.run_frame_with(|cpu| {
+            let outcome = cpu.tick()?;
+            Ok(Some(outcome).filter(|_| cpu.regs()[0] < 6))
+        });
+        assert_eq!(outcome, Ok(None));
+        assert_eq!((chip8.cpu().regs()[0], chip8.cpu().frames()), (6, 1));
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut chip8 = Chip8::new(QuirkProfile::SuperChip);
+        // LD V0, K; SKP V0; JP 0x202; EXIT
+        chip8.load_rom(&program(&[0xF00A, 0xE09E, 0x1202, 0x00FD])).unwrap();
+
+        assert_eq!(chip8.step(), Ok(StepOutcome::WaitingForKey));
+        assert_eq!(chip8.run_frame(), Ok(StepOutcome::WaitingForKey));
+        assert_eq!((chip8.cpu().pc(), chip8.cpu().frames()), (0x200, 1));
+
+        // the key only counts once it is released again
+        chip8.set_key(0x7, true);
+        assert_eq!(chip8.run_frame(), Ok(StepOutcome::WaitingForKey));
+        chip8.set_key(0x7, false);
+        assert!(matches!(chip8.step(), Ok(StepOutcome::Executed(_))));
+        assert_eq!(chip8.cpu().regs()[0], 0x7);
+
+        assert!(matches!(chip8.run_frame(), Ok(StepOutcome::Executed(_))));
+        chip8.set_key(0x7, true);
+        assert_eq!(chip8.run_frame(), Ok(StepOutcome::Exited));
+    }
+
+    #[test]
+    fn test_machines_are_independent() {
+        let mut chip8 = Chip8::new(QuirkProfile::Vip);
+        // ADD V0, 0x01; JP 0x200
+        chip8.load_rom(&program(&[0x7001, 0x1200])).unwrap();
+
+        let mut copy = chip8.clone();
+        let copy = std::thread::spawn(move || {
+            for _ in 0..10 {
+                copy.step().unwrap();
+            }
+            copy
+        })
+        .join()
+        .unwrap();
+
+        chip8.step().unwrap();
+        assert_eq!(chip8.cpu().regs()[0], 1);
+        assert_eq!(copy.cpu().regs()[0], 5);
+    }
+
+    #[test]
+    fn test_rom_too_large() {
+        let mut chip8 = Chip8::new(QuirkProfile::Vip);
+
+        assert_eq!(chip8.load_rom(&[0; 0xE01]), Err(EmuError::RomTooLarge { size: 0xE01, max: 0xE00 }));
+        assert!(Chip8::new(QuirkProfile::XoChip).load_rom(&[0; 0xE01]).is_ok());
+    }
+}
@@ -0,0 +1,361 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use emulator::audio::{Tone, Waveform};
+use emulator::quirks::QuirkProfile;
+use emulator::trace::{OpcodeClass, TraceFilter, TraceFormat};
+
+pub const USAGE: &str = "\
+Usage: emulator [OPTIONS] <ROM>
+
+Options:
+    -s, --scale <N>        Window scale factor, 1-255 (default: 20)
+    -i, --ipf <N>          Instructions executed per 60 Hz frame (default: 8)
+    -c, --ips <N>          Instructions executed per second, rounded down to a whole
+                           number per frame
+    -q, --quirks <NAME>    Quirk profile: vip, chip48, schip or xochip (default: vip)
+        --seed <N>         Seed for the RND instruction, to make runs repeatable (default: random)
+        --record <FILE>    Record keypad input to a movie file
+        --play <FILE>      Play back keypad input from a movie file, then continue live
+        --rewind <MB>      Memory kept for rewinding, 0 to disable (default: 16)
+        --keymap <FILE>    Load key bindings from an INI file, see below
+        --frequency <HZ>   Buzzer tone frequency (default: 440)
+        --waveform <NAME>  Buzzer waveform: square, sine, triangle or sawtooth (default: square)
+        --volume <V>       Buzzer volume from 0.0 to 1.0 (default: 0.25)
+        --mute             Start with the buzzer muted
+        --vsync            Present frames in step with the monitor's refresh
+        --trace <FILE>     Log every executed instruction and the registers it changed
+        --trace-format <F> Trace format: json (one object per line) or csv (default: json)
+        --trace-pc <A-B>   Only trace instructions at addresses A to B, in hex
+        --trace-class <C>  Only trace these comma separated opcode classes: flow, alu,
+                           memory, draw, input, timer or sound
+    -d, --disassemble      Print a disassembly of the ROM and exit
+    -g, --debug            Start paused in the interactive debugger
+    -h, --help             Print this message and exit
+
+Hotkeys:
+    P                      Pause or resume
+    Tab (hold)             Fast forward
+    M                      Toggle buzzer mute
+    Shift+F1 .. Shift+F4   Save state to slot 1-4 (<ROM>.state1 ..)
+    F1 .. F4               Load state from slot 1-4
+    Backspace (hold)       Rewind
+    F12                    Break into the debugger (with --debug)
+
+Keymap files:
+    [keys] rebinds all sixteen keys, [rom.<FILE>] rebinds some keys for the ROM
+    with that file name. Each line binds one key to host keys, separated by
+    commas. Keyboard keys are SDL scancode names (the comma key is written
+    Comma). Controller buttons are Pad <BUTTON> for any controller, or
+    Pad<N> <BUTTON> for player N, with buttons named a, b, x, y, back, start,
+    leftshoulder, rightshoulder, dpup, dpdown, dpleft or dpright. Controllers
+    become players in the order they are plugged in. By default the d-pad
+    presses 2/4/6/8 and A presses 5.
+        [keys]
+        1 = 1
+        4 = Q, Left, Pad dpleft
+        ...
+        [rom.pong.ch8]
+        1 = Pad1 dpup
+        C = Pad2 dpup";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Run,
+    Debug,
+    Disassemble,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieOption {
+    Record(String),
+    Play(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub rom: String,
+    pub scale: u8,
+    pub ipf: u32,
+    pub quirks: QuirkProfile,
+    pub seed: Option<u64>,
+    pub movie: Option<MovieOption>,
+    pub rewind_mb: u32,
+    pub keymap: Option<String>,
+    pub tone: Tone,
+    pub mute: bool,
+    pub vsync: bool,
+    pub trace: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+    pub mode: Mode,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CliError {
+    Help,
+    MissingRom,
+    UnknownFlag(String),
+    UnexpectedArgument(String),
+    MissingValue(String),
+    InvalidValue { flag: String, value: String, expected: &'static str },
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "{}", USAGE),
+            CliError::MissingRom => write!(f, "no ROM file given"),
+            CliError::UnknownFlag(flag) => write!(f, "unknown option '{}'", flag),
+            CliError::UnexpectedArgument(arg) => write!(f, "unexpected argument '{}'", arg),
+            CliError::MissingValue(flag) => write!(f, "option '{}' needs a value", flag),
+            CliError::InvalidValue { flag, value, expected } => {
+                write!(f, "invalid value '{}' for '{}': expected {}", value, flag, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>, expected: &'static str) -> Result<T, CliError> {
+    let value = value.ok_or_else(|| CliError::MissingValue(flag.to_string()))?;
+
+    value.parse().map_err(|_| CliError::InvalidValue {
+        flag: flag.to_string(),
+        value,
+        expected,
+    })
+}
+
+fn parse_pc_range(value: &str) -> Option<RangeInclusive<u16>> {
+    let parse_addr = |addr: &str| {
+        let digits = addr.trim().trim_start_matches("0x").trim_start_matches("0X");
+        u16::from_str_radix(digits, 16).ok()
+    };
+
+    let (start, end) = value.split_once('-')?;
+    let (start, end) = (parse_addr(start)?, parse_addr(end)?);
+
+    Some(start..=end).filter(|_| start <= end)
+}
+
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, CliError> {
+    let mut args = args.into_iter();
+
+    let mut rom = None;
+    let mut scale = 20;
+    let mut ipf = 8;
+    let mut quirks = QuirkProfile::Vip;
+    let mut seed = None;
+    let mut movie = None;
+    let mut rewind_mb = 16;
+    let mut keymap = None;
+    let mut tone = Tone::default();
+    let mut mute = false;
+    let mut vsync = false;
+    let mut trace = None;
+    let mut trace_format = TraceFormat::JsonLines;
+    let mut trace_filter = TraceFilter::default();
+    let mut mode = Mode::Run;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "-d" | "--disassemble" => mode = Mode::Disassemble,
+            "-g" | "--debug" => mode = Mode::Debug,
+            "-s" | "--scale" => {
+                scale = parse_value(&arg, args.next(), "an integer from 1 to 255")?;
+                if scale == 0 {
+                    return Err(CliError::InvalidValue { flag: arg, value: "0".to_string(), expected: "an integer from 1 to 255" });
+                }
+            }
+            "-i" | "--ipf" => {
+                ipf = parse_value(&arg, args.next(), "a positive integer")?;
+                if ipf == 0 {
+                    return Err(CliError::InvalidValue { flag: arg, value: "0".to_string(), expected: "a positive integer" });
+                }
+            }
+            "-c" | "--ips" => {
+                let ips: u32 = parse_value(&arg, args.next(), "an integer of at least 60")?;
+                if ips < 60 {
+                    return Err(CliError::InvalidValue { flag: arg, value: ips.to_string(), expected: "an integer of at least 60" });
+                }
+                ipf = ips / 60;
+            }
+            "-q" | "--quirks" => quirks = parse_value(&arg, args.next(), "one of vip, chip48, schip, xochip")?,
+            "--seed" => seed = Some(parse_value(&arg, args.next(), "an unsigned 64-bit integer")?),
+            "--record" | "--play" => {
+                let path = args.next().ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+                if movie.is_some() {
+                    return Err(CliError::InvalidValue { flag: arg, value: path, expected: "a single --record or --play" });
+                }
+                movie = Some(if arg == "--record" { MovieOption::Record(path) } else { MovieOption::Play(path) });
+            }
+            "--rewind" => rewind_mb = parse_value(&arg, args.next(), "a size in megabytes")?,
+            "--keymap" => keymap = Some(args.next().ok_or_else(|| CliError::MissingValue(arg.clone()))?),
+            "--frequency" => {
+                tone.frequency = parse_value(&arg, args.next(), "a frequency from 20 to 20000 Hz")?;
+                if !(20.0..=20000.0).contains(&tone.frequency) {
+                    return Err(CliError::InvalidValue { flag: arg, value: tone.frequency.to_string(), expected: "a frequency from 20 to 20000 Hz" });
+                }
+            }
+            "--waveform" => tone.waveform = parse_value::<Waveform>(&arg, args.next(), "one of square, sine, triangle, sawtooth")?,
+            "--volume" => {
+                tone.volume = parse_value(&arg, args.next(), "a number from 0.0 to 1.0")?;
+                if !(0.0..=1.0).contains(&tone.volume) {
+                    return Err(CliError::InvalidValue { flag: arg, value: tone.volume.to_string(), expected: "a number from 0.0 to 1.0" });
+                }
+            }
+            "--mute" => mute = true,
+            "--vsync" => vsync = true,
+            "--trace" => trace = Some(args.next().ok_or_else(|| CliError::MissingValue(arg.clone()))?),
+            "--trace-format" => trace_format = parse_value(&arg, args.next(), "one of json, csv")?,
+            "--trace-pc" => {
+                let value = args.next().ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+                match parse_pc_range(&value) {
+                    Some(range) => trace_filter.pc_range = Some(range),
+                    None => return Err(CliError::InvalidValue { flag: arg, value, expected: "a range of hex addresses like 200-2ff" }),
+                }
+            }
+            "--trace-class" => {
+                let value = args.next().ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+                for class in value.split(',') {
+                    trace_filter.classes.push(parse_value::<OpcodeClass>(&arg, Some(class.trim().to_string()), "flow, alu, memory, draw, input, timer or sound")?);
+                }
+            }
+            flag if flag.starts_with('-') && flag.len() > 1 => return Err(CliError::UnknownFlag(arg)),
+            _ => {
+                if rom.is_some() {
+                    return Err(CliError::UnexpectedArgument(arg));
+                }
+                rom = Some(arg);
+            }
+        }
+    }
+
+    Ok(Options {
+        rom: rom.ok_or(CliError::MissingRom)?,
+        scale,
+        ipf,
+        quirks,
+        seed,
+        movie,
+        rewind_mb,
+        keymap,
+        tone,
+        mute,
+        vsync,
+        trace,
+        trace_format,
+        trace_filter,
+        mode,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, CliError> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let options = parse(&["roms/maze.ch8"]).unwrap();
+
+        assert_eq!(options.rom, "roms/maze.ch8");
+        assert_eq!(options.scale, 20);
+        assert_eq!(options.ipf, 8);
+        assert_eq!(options.quirks, QuirkProfile::Vip);
+        assert_eq!(options.seed, None);
+        assert_eq!(options.rewind_mb, 16);
+        assert_eq!(options.keymap, None);
+        assert!(!options.vsync);
+        assert_eq!(options.mode, Mode::Run);
+    }
+
+    #[test]
+    fn test_all_options() {
+        let options = parse(&["-s", "10", "--ips", "1000", "--quirks", "schip", "--seed", "1234", "--rewind", "0", "-d", "game.ch8"]).unwrap();
+
+        assert_eq!(options.rom, "game.ch8");
+        assert_eq!(options.scale, 10);
+        assert_eq!(options.ipf, 16);
+        assert_eq!(options.quirks, QuirkProfile::SuperChip);
+        assert_eq!(options.seed, Some(1234));
+        assert_eq!(options.movie, None);
+        assert_eq!(options.rewind_mb, 0);
+        assert_eq!(options.mode, Mode::Disassemble);
+    }
+
+    #[test]
+    fn test_audio_options() {
+        let options = parse(&["--frequency", "220", "--waveform", "sine", "--volume", "0.5", "--mute", "a.ch8"]).unwrap();
+
+        assert_eq!(options.tone, Tone { frequency: 220.0, waveform: Waveform::Sine, volume: 0.5 });
+        assert!(options.mute);
+
+        assert_eq!(parse(&["--debug", "a.ch8"]).unwrap().mode, Mode::Debug);
+
+        assert!(matches!(parse(&["--volume", "2", "a.ch8"]), Err(CliError::InvalidValue { .. })));
+        assert!(matches!(parse(&["--waveform", "noise", "a.ch8"]), Err(CliError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse(&[]), Err(CliError::MissingRom));
+        assert_eq!(parse(&["--bogus", "a.ch8"]), Err(CliError::UnknownFlag("--bogus".to_string())));
+        assert_eq!(parse(&["a.ch8", "b.ch8"]), Err(CliError::UnexpectedArgument("b.ch8".to_string())));
+        assert_eq!(parse(&["a.ch8", "--scale"]), Err(CliError::MissingValue("--scale".to_string())));
+        assert!(matches!(parse(&["--scale", "0", "a.ch8"]), Err(CliError::InvalidValue { .. })));
+        assert!(matches!(parse(&["--scale", "300", "a.ch8"]), Err(CliError::InvalidValue { .. })));
+        assert!(matches!(parse(&["--quirks", "nes", "a.ch8"]), Err(CliError::InvalidValue { .. })));
+        assert!(matches!(parse(&["--seed", "-1", "a.ch8"]), Err(CliError::InvalidValue { .. })));
+        assert!(matches!(parse(&["--record", "a.mov", "--play", "b.mov", "a.ch8"]), Err(CliError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn test_movie_options() {
+        assert_eq!(parse(&["--record", "a.mov", "a.ch8"]).unwrap().movie, Some(MovieOption::Record("a.mov".to_string())));
+        assert_eq!(parse(&["--play", "b.mov", "a.ch8"]).unwrap().movie, Some(MovieOption::Play("b.mov".to_string())));
+        assert_eq!(parse(&["a.ch8", "--play"]), Err(CliError::MissingValue("--play".to_string())));
+    }
+
+    #[test]
+    fn test_frame_options() {
+        assert_eq!(parse(&["--ipf", "30", "a.ch8"]).unwrap().ipf, 30);
+        assert_eq!(parse(&["-c", "700", "a.ch8"]).unwrap().ipf, 11);
+        assert!(parse(&["--vsync", "a.ch8"]).unwrap().vsync);
+        assert!(matches!(parse(&["--ipf", "0", "a.ch8"]), Err(CliError::InvalidValue { .. })));
+        assert!(matches!(parse(&["--ips", "59", "a.ch8"]), Err(CliError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn test_trace_options() {
+        let options = parse(&["--trace", "out.csv", "--trace-format", "csv", "--trace-pc", "0x200-2FF", "--trace-class", "flow, draw", "a.ch8"]).unwrap();
+
+        assert_eq!(options.trace, Some("out.csv".to_string()));
+        assert_eq!(options.trace_format, TraceFormat::Csv);
+        assert_eq!(options.trace_filter.pc_range, Some(0x200..=0x2FF));
+        assert_eq!(options.trace_filter.classes, vec![OpcodeClass::Flow, OpcodeClass::Draw]);
+
+        let options = parse(&["a.ch8"]).unwrap();
+        assert_eq!(options.trace, None);
+        assert_eq!(options.trace_format, TraceFormat::JsonLines);
+        assert_eq!(options.trace_filter, TraceFilter::default());
+
+        assert!(matches!(parse(&["--trace-pc", "300-200", "a.ch8"]), Err(CliError::InvalidValue { .. })));
+        assert!(matches!(parse(&["--trace-pc", "200", "a.ch8"]), Err(CliError::InvalidValue { .. })));
+        assert!(matches!(parse(&["--trace-class", "flow,io", "a.ch8"]), Err(CliError::InvalidValue { .. })));
+        assert!(matches!(parse(&["--trace-format", "xml", "a.ch8"]), Err(CliError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn test_keymap_option() {
+        assert_eq!(parse(&["--keymap", "keys.ini", "a.ch8"]).unwrap().keymap, Some("keys.ini".to_string()));
+        assert_eq!(parse(&["a.ch8", "--keymap"]), Err(CliError::MissingValue("--keymap".to_string())));
+    }
+}
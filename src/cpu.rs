@@ -1,21 +1,47 @@
-use crate::display::Display;
-use crate::keypad::Keypad;
-use crate::ram::Ram;
+use crate::audio::Audio;
+use crate::display::{Display, MAX_HEIGHT, MAX_WIDTH, PLANE_COUNT};
+use crate::error::EmuError;
+use crate::instruction::{decode, disassemble, Instruction, InstructionSet};
+use crate::keypad::{Keypad, KeypadState};
+use crate::quirks::Quirks;
+use crate::ram::{Ram, BIG_FONT_ADDR, FONT_ADDR};
+use crate::rewind::RewindBuffer;
+use crate::rng::{Rng, SeededRng};
+use crate::state::{SaveState, StateError};
 
 use std::convert::TryFrom;
 use std::fs::File;
 use std::io::Read;
-use std::time::Duration;
-use std::time::Instant;
 
-use rand;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed(Instruction),
+    // Fx0A is still waiting for a key to be pressed and released, and runs
+    // again on the next step. Timers and the display keep going meanwhile.
+    WaitingForKey,
+    Exited,
+}
+
+pub const DEFAULT_CYCLES_PER_FRAME: u32 = 8;
 
-const TIME_PER_TIMER_DECREMENT: Duration = Duration::from_nanos(1_000_000_000 / 60);
+// Progress of an Fx0A wait. Keys that were already down when the wait began
+// are ignored until they come up, so only a new press followed by its release
+// ends the wait.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyWait {
+    pub ignored: u16, // one bit per key
+    pub pressed: Option<u8>,
+}
 
-pub struct Cpu<'a> {
-    ram: &'a mut Ram,
-    display: &'a mut Display,
-    keypad: &'a mut Keypad,
+// A whole machine. It owns its memory and peripherals, so it can be stored,
+// cloned or sent to another thread whenever the peripherals allow it.
+#[derive(Clone)]
+pub struct Cpu<D, K, A> {
+    ram: Ram,
+    display: D,
+    keypad: K,
+    audio: A,
+    keys: KeypadState,
     regs: [u8; 16],
     pc: u16,
     stack: [u16; 16],
@@ -23,15 +49,29 @@ pub struct Cpu<'a> {
     i: u16,
     dt: u8,
     st: u8,
-    last_timer_decrement: std::time::Instant,
+    rpl: [u8; 16],
+    planes: u8,
+    pattern: Option<[u8; 16]>,
+    pitch: u8,
+    cycles_per_frame: u32,
+    frame_cycle: u32,
+    cycles: u64,
+    frames: u64,
+    quirks: Quirks,
+    drawn_this_frame: bool,
+    key_wait: Option<KeyWait>,
+    rng: Box<dyn Rng>,
+    rewind: Option<RewindBuffer>,
 }
 
-impl<'a> Cpu<'a> {
-    pub fn new(ram: &'a mut Ram, display: &'a mut Display, keypad: &'a mut Keypad) -> Self {
+impl<D: Display, K: Keypad, A: Audio> Cpu<D, K, A> {
+    pub fn new(ram: Ram, display: D, keypad: K, audio: A) -> Self {
         Cpu {
             ram,
             display,
             keypad,
+            audio,
+            keys: KeypadState::default(),
             regs: [0; 16],
             pc: 0x200,
             stack: [0; 16],
@@ -39,276 +79,544 @@ impl<'a> Cpu<'a> {
             i: 0x0,
             dt: 0x0,
             st: 0x0,
-            last_timer_decrement: Instant::now(),
+            rpl: [0; 16],
+            planes: 0x1,
+            pattern: None,
+            pitch: 64,
+            cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+            frame_cycle: 0,
+            cycles: 0,
+            frames: 0,
+            quirks: Quirks::default(),
+            drawn_this_frame: false,
+            key_wait: None,
+            rng: Box::new(SeededRng::from_entropy()),
+            rewind: None,
+        }
+    }
+
+    pub fn ram(&self) -> &Ram {
+        &self.ram
+    }
+
+    pub fn display(&self) -> &D {
+        &self.display
+    }
+
+    pub fn display_mut(&mut self) -> &mut D {
+        &mut self.display
+    }
+
+    pub fn keypad(&self) -> &K {
+        &self.keypad
+    }
+
+    pub fn keypad_mut(&mut self) -> &mut K {
+        &mut self.keypad
+    }
+
+    pub fn audio(&self) -> &A {
+        &self.audio
+    }
+
+    pub fn audio_mut(&mut self) -> &mut A {
+        &mut self.audio
+    }
+
+    // The keys as last polled, which is what the program sees
+    pub fn keys(&self) -> KeypadState {
+        self.keys
+    }
+
+    pub fn into_parts(self) -> (Ram, D, K, A) {
+        (self.ram, self.display, self.keypad, self.audio)
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn set_rng(&mut self, rng: Box<dyn Rng>) {
+        self.rng = rng;
+    }
+
+    // Timers count down once every emulated 60 Hz frame, which is a fixed
+    // number of executed instructions. The host decides how fast frames run.
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.cycles_per_frame
+    }
+
+    pub fn set_cycles_per_frame(&mut self, cycles: u32) {
+        assert!(cycles > 0);
+        self.cycles_per_frame = cycles;
+    }
+
+    // Keeps up to `bytes` of history for rewind, or none if 0
+    pub fn set_rewind_budget(&mut self, bytes: usize) {
+        self.rewind = if bytes > 0 { Some(RewindBuffer::new(bytes)) } else { None };
+    }
+
+    pub fn rewind_frames(&self) -> usize {
+        self.rewind.as_ref().map_or(0, RewindBuffer::frames)
+    }
+
+    // Goes back up to `frames` emulated frames and returns how many it went
+    pub fn rewind(&mut self, frames: usize) -> usize {
+        let mut buffer = match self.rewind.take() {
+            Some(buffer) => buffer,
+            None => return 0,
+        };
+
+        let rewound = buffer.rewind(frames);
+        if rewound > 0 {
+            let state = SaveState::from_bytes(buffer.current().unwrap()).expect("rewind buffer holds valid states");
+            self.restore(&state);
+            self.audio.set_beeping(false);
         }
+        self.rewind = Some(buffer);
+
+        rewound
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
     }
 
     pub fn load_rom_into_ram(&mut self, filename: &str) -> Result<(), std::io::Error>{
-        let f = File::open(filename)?;
+        let mut rom = Vec::new();
+        File::open(filename)?.read_to_end(&mut rom)?;
 
-        for (i, byte) in f.bytes().enumerate() {
-            self.ram.set(u16::try_from(i).unwrap() + 0x200, byte.unwrap());
+        self.load_rom(&rom).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))
+    }
+
+    // Copies a ROM image to 0x200, where programs start
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), EmuError> {
+        if rom.len() > self.ram.size() - 0x200 {
+            return Err(EmuError::RomTooLarge { size: rom.len(), max: self.ram.size() - 0x200 });
+        }
+
+        for (i, &byte) in rom.iter().enumerate() {
+            self.ram.set(u16::try_from(i).unwrap() + 0x200, byte);
         }
 
         Ok(())
     }
 
-    pub fn disas(&self) {
-        for pc in (0x200..0x1000).step_by(2) {
-            print!("[0x{:04x}]: ", pc);
-
-            let op = self.opcode_at(pc);
-            self.print_opcode(op);
-        }
-    }
-
-    pub fn print_opcode(&self, opcode: u16) {
-        let nnn = opcode & 0x0FFF;
-        let nn = opcode & 0x00FF;
-        let nibbles = (
-                (opcode & 0xF000) >> 12,
-                (opcode & 0x0F00) >> 8,
-                (opcode & 0x00F0) >> 4,
-                (opcode & 0x000F)
-            );
-
-        match nibbles {
-            (0x0, 0x0, 0xE, 0x0) => println!("CLS"),
-            (0x0, 0x0, 0xE, 0xE) => println!("RET"),
-            (0x1, _, _, _) => println!("JP 0x{:04x}", nnn),
-            (0x2, _, _, _) => println!("CALL 0x{:04x}", nnn),
-            (0x3, x, _, _) => println!("SE V{:01x}, 0x{:02x}", x, nn),
-            (0x4, x, _, _) => println!("SNE V{:01x}, 0x{:02x}", x, nn),
-            (0x5, x, y, 0x0) => println!("SE V{:01x}, V{:01x}", x, y),
-            (0x6, x, _, _) => println!("LD V{:01x}, 0x{:02}", x, nn),
-            (0x7, x, _, _) => println!("ADD V{:01x}, 0x{:02}", x, nn),
-            (0x8, x, y, 0x0) => println!("LD V{:01x}, V{:01x}", x, y),
-            (0x8, x, y, 0x1) => println!("OR V{:01x}, V{:01x}", x, y),
-            (0x8, x, y, 0x2) => println!("AND V{:01x}, V{:01x}", x, y),
-            (0x8, x, y, 0x3) => println!("XOR V{:01x}, V{:01x}", x, y),
-            (0x8, x, y, 0x4) => println!("ADD V{:01x}, V{:01x}", x, y),
-            (0x8, x, y, 0x5) => println!("SUB V{:01x}, V{:01x}", x, y),
-            (0x8, x, _, 0x6) => println!("SHR V{:01x}", x),
-            (0x8, x, y, 0x7) => println!("SUBN V{:01x}, V{:01x}", x, y),
-            (0x8, x, _, 0xE) => println!("SHL V{:01x}", x),
-            (0x9, x, y, 0x0) => println!("SNE V{:01x}, V{:01x}", x, y),
-            (0xA, _, _, _) => println!("LD I, 0x{:04x}", nnn),
-            (0xB, _, _, _) => println!("JP V00, 0x{:04x}", nnn),
-            (0xC, x, _, _) => println!("RND V{:01x}, 0x{:02x}", x, nn),
-            (0xD, x, y, n) => println!("DRW V{:01x}, V{:01x}, 0x{:01x}", x, y, n),
-            (0xE, x, 0x9, 0xE) => println!("SKP V{:01x}", x),
-            (0xE, x, 0xA, 0x1) => println!("SKNP V{:01x}", x),
-            (0xF, x, 0x0, 0x7) => println!("LD V{:01x}, DT", x),
-            (0xF, x, 0x0, 0xA) => println!("LD V{:01x}, K", x),
-            (0xF, x, 0x1, 0x5) => println!("LD DT, V{:01x}", x),
-            (0xF, x, 0x1, 0x8) => println!("LD ST, V{:01x}", x),
-            (0xF, x, 0x1, 0xE) => println!("ADD I, V{:01x}", x),
-            (0xF, x, 0x2, 0x9) => println!("LD F, V{:01x}", x),
-            (0xF, x, 0x3, 0x3) => println!("LD B, V{:01x}", x),
-            (0xF, x, 0x5, 0x5) => println!("LD [I], V{:01x}", x),
-            (0xF, x, 0x6, 0x5) => println!("LD V{:01x}, [I]", x),
-            (_, _, _, _) => println!("UNKNOWN OPCODE: (0x{:02x}, 0x{:02x}, 0x{:02x}, 0x{:02x})", nibbles.0, nibbles.1, nibbles.2, nibbles.3),
-        };
+    pub fn snapshot(&self) -> SaveState {
+        let mut vram = [[0; MAX_HEIGHT]; MAX_WIDTH];
+        for x in 0..self.display.width() {
+            for y in 0..self.display.height() {
+                vram[usize::from(x)][usize::from(y)] = self.display.vram_get(x, y);
+            }
+        }
 
-        //println!("(0x{:02x}, 0x{:02x}, 0x{:02x}, 0x{:02x})", nibbles.0, nibbles.1, nibbles.2, nibbles.3);
+        SaveState {
+            regs: self.regs,
+            pc: self.pc,
+            stack: self.stack,
+            sp: self.sp,
+            i: self.i,
+            dt: self.dt,
+            st: self.st,
+            rpl: self.rpl,
+            hires: self.display.hires(),
+            planes: self.planes,
+            pattern: self.pattern,
+            pitch: self.pitch,
+            frame_cycle: self.frame_cycle,
+            cycles: self.cycles,
+            frames: self.frames,
+            drawn_this_frame: self.drawn_this_frame,
+            key_wait: self.key_wait,
+            rng_state: self.rng.state(),
+            ram: (0..self.ram.size()).map(|addr| self.ram.read(addr as u16)).collect(),
+            vram,
+        }
     }
 
-    fn opcode_at(&self, addr: u16) -> u16 {
+    pub fn restore(&mut self, state: &SaveState) {
+        assert_eq!(state.ram.len(), self.ram.size());
+
+        self.regs = state.regs;
+        self.pc = state.pc;
+        self.stack = state.stack;
+        self.sp = state.sp;
+        self.i = state.i;
+        self.dt = state.dt;
+        self.st = state.st;
+        self.rpl = state.rpl;
+        self.planes = state.planes;
+        self.pattern = state.pattern;
+        self.pitch = state.pitch;
+        self.audio.set_pattern(self.pattern, self.pitch);
+        self.frame_cycle = state.frame_cycle;
+        self.cycles = state.cycles;
+        self.frames = state.frames;
+        self.drawn_this_frame = state.drawn_this_frame;
+        self.key_wait = state.key_wait;
+        self.rng.set_state(state.rng_state);
+
+        for (addr, &byte) in state.ram.iter().enumerate() {
+            self.ram.set(addr as u16, byte);
+        }
+
+        self.display.set_hires(state.hires);
+        for x in 0..self.display.width() {
+            for y in 0..self.display.height() {
+                self.display.vram_set(x, y, state.vram[usize::from(x)][usize::from(y)]);
+            }
+        }
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        self.snapshot().to_bytes()
+    }
+
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let state = SaveState::from_bytes(bytes)?;
+        if state.ram.len() != self.ram.size() {
+            return Err(StateError::Corrupt("memory size does not match the running machine"));
+        }
+        self.restore(&state);
+
+        Ok(())
+    }
+
+    // Prints the first `len` bytes of the program at 0x200, normally the size of the ROM
+    pub fn disas(&self, len: usize) {
+        let end = (0x200 + len).min(self.ram.size());
+        let program: Vec<u8> = (0x200..end).map(|addr| self.ram.read(addr as u16)).collect();
+
+        for line in disassemble(&program, 0x200) {
+            println!("{}", line);
+        }
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn dt(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn st(&self) -> u8 {
+        self.st
+    }
+
+    pub fn regs(&self) -> &[u8; 16] {
+        &self.regs
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..usize::from(self.sp)]
+    }
+
+    pub fn memory_size(&self) -> usize {
+        self.ram.size()
+    }
+
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.ram.read(addr)
+    }
+
+    pub fn opcode_at(&self, addr: u16) -> u16 {
         (u16::from(self.ram.read(addr)) << 8) | u16::from(self.ram.read(addr + 1))
     }
 
-    pub fn tick(&mut self) {
+    pub fn tick(&mut self) -> Result<StepOutcome, EmuError> {
+        self.check_range(self.pc, 2)?;
         let op = self.opcode_at(self.pc);
 
-        print!("[0x{:04x}]: ", self.pc);
-        self.print_opcode(op);
+        if self.keypad.check_for_exit() {
+            return Err(EmuError::UserQuit);
+        }
 
-        while self.last_timer_decrement.elapsed() > TIME_PER_TIMER_DECREMENT {
-            if self.dt > 0 {
-                self.dt -= 1;
-            }
+        let instruction = self.run_opcode(op)?;
+        if instruction == Instruction::Exit {
+            return Ok(StepOutcome::Exited);
+        }
 
-            if self.st > 0 {
-                self.st -= 1;
-            }
+        self.cycles += 1;
+        self.frame_cycle += 1;
+        if self.frame_cycle >= self.cycles_per_frame {
+            self.end_frame()?;
+        }
+
+        // presenting is left to the front end, which does it once per frame
+        self.audio.set_beeping(self.st > 0);
+
+        if self.key_wait.is_some() {
+            return Ok(StepOutcome::WaitingForKey);
+        }
+
+        Ok(StepOutcome::Executed(instruction))
+    }
+
+    // Polls the keypad outside of the frame loop, e.g. while rewinding, so the
+    // front end still sees its input and quit requests
+    pub fn poll_input(&mut self) -> Result<(), EmuError> {
+        self.keys = self.keypad.poll();
+        if self.keypad.check_for_exit() {
+            return Err(EmuError::UserQuit);
+        }
+
+        Ok(())
+    }
+
+    fn end_frame(&mut self) -> Result<(), EmuError> {
+        if self.dt > 0 {
+            self.dt -= 1;
+        }
+
+        if self.st > 0 {
+            self.st -= 1;
+        }
+
+        self.frame_cycle = 0;
+        self.frames += 1;
+        self.drawn_this_frame = false;
+
+        if let Some(mut buffer) = self.rewind.take() {
+            buffer.push(self.save_state());
+            self.rewind = Some(buffer);
+        }
+
+        let (ram, display) = (&self.ram, &self.display);
+        self.keypad.end_frame(self.frames, &|| hash_machine(ram, display))?;
+        self.keys = self.keypad.poll();
+
+        Ok(())
+    }
 
-            self.last_timer_decrement += TIME_PER_TIMER_DECREMENT;
-        }
-
-        self.keypad.check_for_exit();
-        self.run_opcode(op);
-        self.display.redraw();
-    }
-
-    fn run_opcode(&mut self, opcode: u16) {
-        let nnn = opcode & 0x0FFF;
-        let nn = (opcode & 0x00FF) as u8;
-        let nibbles = (
-                ((opcode & 0xF000) >> 12) as u8,
-                ((opcode & 0x0F00) >> 8) as u8,
-                ((opcode & 0x00F0) >> 4) as u8,
-                (opcode & 0x000F) as u8
-            );
-
-        match nibbles {
-            (0x0, 0x0, 0xE, 0x0) => self.cls(),
-            (0x0, 0x0, 0xE, 0xE) => self.ret(),
-            (0x1, _, _, _) => self.jp_addr(nnn),
-            (0x2, _, _, _) => self.call_addr(nnn),
-            (0x3, x, _, _) => self.se_vx_byte(x, nn),
-            (0x4, x, _, _) => self.sne_vx_byte(x, nn),
-            (0x5, x, y, 0x0) => self.se_vx_vy(x, y),
-            (0x6, x, _, _) => self.ld_vx_byte(x, nn),
-            (0x7, x, _, _) => self.add_vx_byte(x, nn),
-            (0x8, x, y, 0x0) => self.load_vx_vy(x, y),
-            (0x8, x, y, 0x1) => self.or_vx_vy(x, y),
-            (0x8, x, y, 0x2) => self.and_vx_vy(x, y),
-            (0x8, x, y, 0x3) => self.xor_vx_vy(x, y),
-            (0x8, x, y, 0x4) => self.add_vx_vy(x, y),
-            (0x8, x, y, 0x5) => self.sub_vx_vy(x, y),
-            (0x8, x, _, 0x6) => self.shr_vx(x),
-            (0x8, x, y, 0x7) => self.subn_vx_vy(x, y),
-            (0x8, x, _, 0xE) => self.shl_vx(x),
-            (0x9, x, y, 0x0) => self.sne_vx_vy(x, y),
-            (0xA, _, _, _) => self.ld_i_addr(nnn),
-            (0xB, _, _, _) => self.jp_v0_addr(nnn),
-            (0xC, x, _, _) => self.rnd_vx_byte(x, nn),
-            (0xD, x, y, n) => self.drw_vx_vy_nibble(x, y, n),
-            (0xE, x, 0x9, 0xE) => self.skp_vx(x),
-            (0xE, x, 0xA, 0x1) => self.sknp_vx(x),
-            (0xF, x, 0x0, 0x7) => self.ld_vx_dt(x),
-            (0xF, x, 0x0, 0xA) => self.ld_vx_k(x),
-            (0xF, x, 0x1, 0x5) => self.ld_dt_vx(x),
-            (0xF, x, 0x1, 0x8) => self.ld_st_vx(x),
-            (0xF, x, 0x1, 0xE) => self.add_i_vx(x),
-            (0xF, x, 0x2, 0x9) => self.ld_f_vx(x),
-            (0xF, x, 0x3, 0x3) => self.ld_b_vx(x),
-            (0xF, x, 0x5, 0x5) => self.ld_i_vx(x),
-            (0xF, x, 0x6, 0x5) => self.ld_vx_i(x),
-            (_, _, _, _) => panic!("Unknown opcode {:02x?}", nibbles),
+    pub fn state_hash(&self) -> u64 {
+        hash_machine(&self.ram, &self.display)
+    }
+
+    fn check_range(&self, addr: u16, len: u16) -> Result<(), EmuError> {
+        let end = u32::from(addr) + u32::from(len);
+        if end as usize > self.ram.size() {
+            return Err(EmuError::MemoryOutOfBounds { pc: self.pc, addr: end - 1 });
+        }
+
+        Ok(())
+    }
+
+    fn run_opcode(&mut self, opcode: u16) -> Result<Instruction, EmuError> {
+        let instruction = decode(opcode)
+            .ok()
+            .filter(|instruction| instruction.set() <= self.quirks.instruction_set)
+            .ok_or(EmuError::InvalidOpcode { pc: self.pc, opcode })?;
+
+        match instruction {
+            Instruction::Cls => self.cls()?,
+            Instruction::Ret => self.ret()?,
+            Instruction::JpAddr(addr) => self.jp_addr(addr),
+            Instruction::CallAddr(addr) => self.call_addr(addr)?,
+            Instruction::SeVxByte(x, byte) => self.se_vx_byte(x, byte)?,
+            Instruction::SneVxByte(x, byte) => self.sne_vx_byte(x, byte)?,
+            Instruction::SeVxVy(x, y) => self.se_vx_vy(x, y)?,
+            Instruction::LdVxByte(x, byte) => self.ld_vx_byte(x, byte)?,
+            Instruction::AddVxByte(x, byte) => self.add_vx_byte(x, byte)?,
+            Instruction::LdVxVy(x, y) => self.load_vx_vy(x, y)?,
+            Instruction::OrVxVy(x, y) => self.or_vx_vy(x, y)?,
+            Instruction::AndVxVy(x, y) => self.and_vx_vy(x, y)?,
+            Instruction::XorVxVy(x, y) => self.xor_vx_vy(x, y)?,
+            Instruction::AddVxVy(x, y) => self.add_vx_vy(x, y)?,
+            Instruction::SubVxVy(x, y) => self.sub_vx_vy(x, y)?,
+            Instruction::ShrVxVy(x, y) => self.shr_vx_vy(x, y)?,
+            Instruction::SubnVxVy(x, y) => self.subn_vx_vy(x, y)?,
+            Instruction::ShlVxVy(x, y) => self.shl_vx_vy(x, y)?,
+            Instruction::SneVxVy(x, y) => self.sne_vx_vy(x, y)?,
+            Instruction::LdIAddr(addr) => self.ld_i_addr(addr)?,
+            Instruction::JpV0Addr(addr) => self.jp_v0_addr(addr),
+            Instruction::RndVxByte(x, byte) => self.rnd_vx_byte(x, byte)?,
+            Instruction::DrwVxVyNibble(x, y, n) => self.drw_vx_vy_nibble(x, y, n)?,
+            Instruction::SkpVx(x) => self.skp_vx(x)?,
+            Instruction::SknpVx(x) => self.sknp_vx(x)?,
+            Instruction::LdVxDt(x) => self.ld_vx_dt(x)?,
+            Instruction::LdVxK(x) => self.ld_vx_k(x)?,
+            Instruction::LdDtVx(x) => self.ld_dt_vx(x)?,
+            Instruction::LdStVx(x) => self.ld_st_vx(x)?,
+            Instruction::AddIVx(x) => self.add_i_vx(x)?,
+            Instruction::LdFVx(x) => self.ld_f_vx(x)?,
+            Instruction::LdBVx(x) => self.ld_b_vx(x)?,
+            Instruction::LdIVx(x) => self.ld_i_vx(x)?,
+            Instruction::LdVxI(x) => self.ld_vx_i(x)?,
+            Instruction::ScdNibble(n) => self.scd_nibble(n)?,
+            Instruction::Scr => self.scr()?,
+            Instruction::Scl => self.scl()?,
+            Instruction::Exit => {}
+            Instruction::Low => self.set_hires(false)?,
+            Instruction::High => self.set_hires(true)?,
+            Instruction::LdHfVx(x) => self.ld_hf_vx(x)?,
+            Instruction::LdRVx(x) => self.ld_r_vx(x)?,
+            Instruction::LdVxR(x) => self.ld_vx_r(x)?,
+            Instruction::SaveVxVy(x, y) => self.save_vx_vy(x, y)?,
+            Instruction::LoadVxVy(x, y) => self.load_vx_vy_i(x, y)?,
+            Instruction::LdILong => self.ld_i_long()?,
+            Instruction::PlaneN(n) => self.plane_n(n)?,
+            Instruction::LdAudioI => self.ld_audio_i()?,
+            Instruction::LdPitchVx(x) => self.ld_pitch_vx(x)?,
         };
+
+        Ok(instruction)
     }
 
-    fn nop(&mut self) {
-        self.pc += 2;
+    // Moves pc on by `len` bytes. Running off the top of the 64 KiB address
+    // space is an error rather than a wrap back to 0.
+    fn advance(&mut self, len: u16) -> Result<(), EmuError> {
+        self.pc = self.pc.checked_add(len).ok_or(EmuError::MemoryOutOfBounds { pc: self.pc, addr: u32::from(self.pc) + u32::from(len) })?;
+
+        Ok(())
     }
 
-    fn cls(&mut self) {
-        self.display.clear();
-        self.pc += 2;
+    fn cls(&mut self) -> Result<(), EmuError> {
+        self.display.clear_planes(self.planes);
+        self.advance(2)
     }
 
-    fn ret(&mut self) {
-        assert!(self.sp > 0);
+    // XO-CHIP's F000 NNNN is four bytes long, so skipping it has to step over
+    // both words. Earlier machines don't have it and skip F000 on its own.
+    fn skip_next_instruction_if(&mut self, skip: bool) -> Result<(), EmuError> {
+        if !skip {
+            return self.advance(2);
+        }
+
+        let next = u32::from(self.pc) + 2;
+        let long = self.quirks.instruction_set == InstructionSet::XoChip
+            && next as usize + 2 <= self.ram.size()
+            && self.opcode_at(next as u16) == 0xF000;
+        self.advance(if long { 6 } else { 4 })
+    }
+
+    fn ret(&mut self) -> Result<(), EmuError> {
+        if self.sp == 0 {
+            return Err(EmuError::StackUnderflow { pc: self.pc });
+        }
 
         self.sp -= 1;
         self.pc = self.stack[usize::from(self.sp)];
+
+        Ok(())
     }
 
     fn jp_addr(&mut self, addr: u16) {
-        assert!(addr < 4095);
-
-        if self.pc == addr {
-            //panic!("Infinite loop detected. Will exit now.");
-        }
-
         self.pc = addr
     }
 
-    fn call_addr(&mut self, addr: u16) {
-        assert!(addr < 4095);
+    fn call_addr(&mut self, addr: u16) -> Result<(), EmuError> {
+        if usize::from(self.sp) == self.stack.len() {
+            return Err(EmuError::StackOverflow { pc: self.pc });
+        }
 
-        self.stack[usize::from(self.sp)] = self.pc + 2;
+        let ret = self.pc.checked_add(2).ok_or(EmuError::MemoryOutOfBounds { pc: self.pc, addr: u32::from(self.pc) + 2 })?;
+        self.stack[usize::from(self.sp)] = ret;
         self.sp += 1;
 
         self.pc = addr;
+
+        Ok(())
     }
 
-    fn se_vx_byte(&mut self, reg: u8, byte: u8) {
+    fn se_vx_byte(&mut self, reg: u8, byte: u8) -> Result<(), EmuError> {
         assert!(reg < 16);
 
-        if self.regs[usize::from(reg)] == byte {
-            self.pc += 2;
-        }
-
-        self.pc += 2;
+        self.skip_next_instruction_if(self.regs[usize::from(reg)] == byte)
     }
 
-    fn sne_vx_byte(&mut self, reg: u8, byte: u8) {
+    fn sne_vx_byte(&mut self, reg: u8, byte: u8) -> Result<(), EmuError> {
         assert!(reg < 16);
 
-        if self.regs[usize::from(reg)] != byte {
-            self.pc += 2;
-        }
-
-        self.pc += 2;
+        self.skip_next_instruction_if(self.regs[usize::from(reg)] != byte)
     }
 
-    fn se_vx_vy(&mut self, reg1: u8, reg2: u8) {
+    fn se_vx_vy(&mut self, reg1: u8, reg2: u8) -> Result<(), EmuError> {
         assert!(reg1 < 16);
         assert!(reg2 < 16);
 
-        if self.regs[usize::from(reg1)] == self.regs[usize::from(reg2)] {
-            self.pc += 2;
-        }
-
-        self.pc += 2;
+        self.skip_next_instruction_if(self.regs[usize::from(reg1)] == self.regs[usize::from(reg2)])
     }
 
-    fn ld_vx_byte(&mut self, reg: u8, byte: u8) {
+    fn ld_vx_byte(&mut self, reg: u8, byte: u8) -> Result<(), EmuError> {
         assert!(reg < 16);
 
         self.regs[usize::from(reg)] = byte;
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn add_vx_byte(&mut self, reg: u8, byte: u8) {
+    fn add_vx_byte(&mut self, reg: u8, byte: u8) -> Result<(), EmuError> {
         assert!(reg < 16);
 
         self.regs[usize::from(reg)] =
             self.regs[usize::from(reg)].wrapping_add(byte);
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn load_vx_vy(&mut self, vx: u8, vy: u8) {
+    fn load_vx_vy(&mut self, vx: u8, vy: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
         assert!(vy < 16);
 
         self.regs[usize::from(vx)] = self.regs[usize::from(vy)];
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn or_vx_vy(&mut self, vx: u8, vy: u8) {
+    fn or_vx_vy(&mut self, vx: u8, vy: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
         assert!(vy < 16);
 
         self.regs[usize::from(vx)] |= self.regs[usize::from(vy)];
+        if self.quirks.vf_reset {
+            self.regs[0xF] = 0;
+        }
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn and_vx_vy(&mut self, vx: u8, vy: u8) {
+    fn and_vx_vy(&mut self, vx: u8, vy: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
         assert!(vy < 16);
 
         self.regs[vx as usize] &= self.regs[vy as usize];
+        if self.quirks.vf_reset {
+            self.regs[0xF] = 0;
+        }
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn xor_vx_vy(&mut self, vx: u8, vy: u8) {
+    fn xor_vx_vy(&mut self, vx: u8, vy: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
         assert!(vy < 16);
 
         self.regs[vx as usize] ^= self.regs[vy as usize];
+        if self.quirks.vf_reset {
+            self.regs[0xF] = 0;
+        }
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn add_vx_vy(&mut self, vx: u8, vy: u8) {
+    fn add_vx_vy(&mut self, vx: u8, vy: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
         assert!(vy < 16);
 
@@ -318,176 +626,233 @@ impl<'a> Cpu<'a> {
         self.regs[vx as usize] = new_val;
         self.regs[0xF] = u8::from(overflow);
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn sub_vx_vy(&mut self, vx: u8, vy: u8) {
+    fn sub_vx_vy(&mut self, vx: u8, vy: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
         assert!(vy < 16);
 
         let (new_val, overflow) =
             self.regs[vx as usize].overflowing_sub(self.regs[vy as usize]);
 
-        self.regs[0xF] = u8::from(overflow);
+        // VF is set when there is no borrow, and written last in case x is F
         self.regs[vx as usize] = new_val;
+        self.regs[0xF] = u8::from(!overflow);
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn shr_vx(&mut self, vx: u8) {
+    fn shr_vx_vy(&mut self, vx: u8, vy: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
+        assert!(vy < 16);
+
+        let src = if self.quirks.shift_uses_vy { vy } else { vx };
+        let val = self.regs[usize::from(src)];
 
-        self.regs[0xF] = self.regs[usize::from(vx)] & 0b00000001;
-        self.regs[usize::from(vx)] >>= 1;
+        self.regs[usize::from(vx)] = val >> 1;
+        self.regs[0xF] = val & 0b00000001;
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn subn_vx_vy(&mut self, vx: u8, vy: u8) {
+    fn subn_vx_vy(&mut self, vx: u8, vy: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
         assert!(vy < 16);
 
         let (new_val, overflow) =
             self.regs[usize::from(vy)].overflowing_sub(self.regs[usize::from(vx)]);
 
-        self.regs[0xF] = u8::from(overflow);
         self.regs[vx as usize] = new_val;
+        self.regs[0xF] = u8::from(!overflow);
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn shl_vx(&mut self, vx: u8) {
+    fn shl_vx_vy(&mut self, vx: u8, vy: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
+        assert!(vy < 16);
+
+        let src = if self.quirks.shift_uses_vy { vy } else { vx };
+        let val = self.regs[usize::from(src)];
 
-        self.regs[0xF] = self.regs[usize::from(vx)] & 0b10000000;
-        self.regs[usize::from(vx)] <<= 1;
+        self.regs[usize::from(vx)] = val << 1;
+        self.regs[0xF] = val >> 7;
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn sne_vx_vy(&mut self, reg1: u8, reg2: u8) {
+    fn sne_vx_vy(&mut self, reg1: u8, reg2: u8) -> Result<(), EmuError> {
         assert!(reg1 < 16);
         assert!(reg2 < 16);
 
-        if self.regs[usize::from(reg1)] != self.regs[usize::from(reg2)] {
-            self.pc += 2;
-        }
-
-        self.pc += 2;
+        self.skip_next_instruction_if(self.regs[usize::from(reg1)] != self.regs[usize::from(reg2)])
     }
 
-    fn ld_i_addr(&mut self, val: u16) {
+    fn ld_i_addr(&mut self, val: u16) -> Result<(), EmuError> {
         self.i = val;
 
-        self.pc += 2;
+        self.advance(2)
     }
 
     fn jp_v0_addr(&mut self, addr: u16) {
-        self.pc = (self.regs[0] as u16) + addr;
+        let reg = if self.quirks.jump_uses_vx { (addr >> 8) as usize } else { 0 };
+
+        self.pc = (self.regs[reg] as u16) + addr;
     }
 
-    fn rnd_vx_byte(&mut self, reg: u8, byte: u8) {
+    fn rnd_vx_byte(&mut self, reg: u8, byte: u8) -> Result<(), EmuError> {
         assert!(reg < 16);
 
-        self.regs[usize::from(reg)] = rand::random::<u8>() & byte;
+        self.regs[usize::from(reg)] = self.rng.next_byte() & byte;
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn drw_vx_vy_nibble(&mut self, reg1: u8, reg2: u8, nibble: u8) {
+    fn drw_vx_vy_nibble(&mut self, reg1: u8, reg2: u8, nibble: u8) -> Result<(), EmuError> {
         assert!(reg1 < 16);
         assert!(reg2 < 16);
 
-        let x = self.regs[usize::from(reg1)] % 64;
-        let y = self.regs[usize::from(reg2)] % 32;
+        // Dxy0 draws a 16x16 sprite stored as two bytes per row. With several
+        // XO-CHIP planes selected, the sprite for each plane follows the last.
+        let (rows, cols): (u8, u8) = if nibble == 0 { (16, 16) } else { (nibble, 8) };
+        let sprite_len = u16::from(rows) * u16::from(cols / 8);
+        self.check_range(self.i, sprite_len * self.planes.count_ones() as u16)?;
 
-        let mut pixel_erased = false;
-        for yy in 0..nibble {
-            let row_val = self.ram.read(self.i + u16::from(yy));
-            for xx in 0..8 {
-                if x + xx >= 64 || y + yy >= 32 {
-                    continue;
-                }
+        // leaving pc alone re-executes the draw once the next frame starts
+        if self.quirks.display_wait && self.drawn_this_frame {
+            return Ok(());
+        }
 
-                let pixel_flip = (row_val & (0x1 << (7 - xx))) != 0;
-                let old_pixel = self.display.vram_get(x + xx, y + yy);
+        let (width, height) = (self.display.width(), self.display.height());
+        let x = self.regs[usize::from(reg1)] % width;
+        let y = self.regs[usize::from(reg2)] % height;
 
-                pixel_erased |= pixel_flip && old_pixel; // pixel erased when old pixel and pixel will be flipped
-                self.display.vram_set(x + xx, y + yy, old_pixel ^ pixel_flip);
+        let mut pixel_erased = false;
+        let (mut sprite, planes) = (self.i, self.planes);
+        for plane in (0..PLANE_COUNT).map(|plane| 1 << plane).filter(|plane| planes & plane != 0) {
+            for yy in 0..rows {
+                let row_val = if cols == 16 {
+                    u16::from_be_bytes([self.ram.read(sprite + 2 * u16::from(yy)), self.ram.read(sprite + 2 * u16::from(yy) + 1)])
+                } else {
+                    u16::from(self.ram.read(sprite + u16::from(yy))) << 8
+                };
+
+                for xx in 0..cols {
+                    let (px, py) = (u16::from(x) + u16::from(xx), u16::from(y) + u16::from(yy));
+                    if self.quirks.clip_sprites && (px >= u16::from(width) || py >= u16::from(height)) {
+                        continue;
+                    }
+
+                    let (px, py) = ((px % u16::from(width)) as u8, (py % u16::from(height)) as u8);
+                    let pixel_flip = (row_val & (0x8000 >> xx)) != 0;
+                    let old_pixel = self.display.vram_get(px, py);
+
+                    pixel_erased |= pixel_flip && old_pixel & plane != 0; // pixel erased when old pixel and pixel will be flipped
+                    if pixel_flip {
+                        self.display.vram_set(px, py, old_pixel ^ plane);
+                    }
+                }
             }
+
+            sprite = sprite.wrapping_add(sprite_len);
         }
 
         self.regs[0xF] = u8::from(pixel_erased);
+        self.drawn_this_frame = true;
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn skp_vx(&mut self, vx: u8) {
-        if self.keypad.button_is_pressed(self.regs[usize::from(vx)]) {
-            self.pc += 2;
+    fn key_in_vx(&self, vx: u8) -> Result<u8, EmuError> {
+        let key = self.regs[usize::from(vx)];
+        if key > 0xF {
+            return Err(EmuError::InvalidKey { pc: self.pc, key });
         }
 
-        self.pc += 2;
+        Ok(key)
     }
 
-    fn sknp_vx(&mut self, vx: u8) {
-        if !self.keypad.button_is_pressed(self.regs[usize::from(vx)]) {
-            self.pc += 2;
-        }
+    fn skp_vx(&mut self, vx: u8) -> Result<(), EmuError> {
+        let key = self.key_in_vx(vx)?;
+        self.skip_next_instruction_if(self.keys.is_pressed(key))
+    }
 
-        self.pc += 2;
+    fn sknp_vx(&mut self, vx: u8) -> Result<(), EmuError> {
+        let key = self.key_in_vx(vx)?;
+        self.skip_next_instruction_if(!self.keys.is_pressed(key))
     }
 
-    fn ld_vx_dt(&mut self, vx: u8) {
+    fn ld_vx_dt(&mut self, vx: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
 
         self.regs[usize::from(vx)] = self.dt;
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn ld_vx_k(&mut self, vx: u8) {
+    // Leaves pc alone until the wait is over, so the instruction runs again
+    // on every step and the rest of the machine keeps going
+    fn ld_vx_k(&mut self, vx: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
 
-        self.regs[usize::from(vx)] = self.keypad.next_button_pressed();
+        let held = self.keys.bits();
+
+        let mut wait = self.key_wait.unwrap_or(KeyWait { ignored: held, pressed: None });
+        wait.ignored &= held;
+        match wait.pressed {
+            Some(key) if !self.keys.is_pressed(key) => {
+                self.key_wait = None;
+                self.regs[usize::from(vx)] = key;
+                return self.advance(2);
+            }
+            Some(_) => {}
+            None => wait.pressed = KeypadState::from_bits(held & !wait.ignored).pressed_keys().next(),
+        }
 
-        self.pc += 2;
+        self.key_wait = Some(wait);
+
+        Ok(())
     }
 
-    fn ld_dt_vx(&mut self, vx: u8) {
+    fn ld_dt_vx(&mut self, vx: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
 
         self.dt = self.regs[usize::from(vx)];
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn ld_st_vx(&mut self, vx: u8) {
+    fn ld_st_vx(&mut self, vx: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
 
         self.st = self.regs[usize::from(vx)];
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn add_i_vx(&mut self, vx: u8) {
+    fn add_i_vx(&mut self, vx: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
 
-        self.i += self.regs[usize::from(vx)] as u16;
+        // I is 16 bits wide and wraps, as on XO-CHIP
+        self.i = self.i.wrapping_add(u16::from(self.regs[usize::from(vx)]));
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn ld_f_vx(&mut self, vx: u8) {
-        println!("Getting font for: {} at: {}", self.regs[usize::from(vx)], self.regs[usize::from(vx)] * 5);
-        self.i = u16::from(self.regs[usize::from(vx)] * 5);
+    fn ld_f_vx(&mut self, vx: u8) -> Result<(), EmuError> {
+        assert!(vx < 16);
 
-        self.pc += 2;
+        // only the low nibble picks a glyph, so the address can't overflow
+        let addr = FONT_ADDR + u16::from(self.regs[usize::from(vx)] & 0xF) * 5;
+        self.i = addr;
+
+        self.advance(2)
     }
 
-    fn ld_b_vx(&mut self, vx: u8) {
-        assert!(self.i < 4095);
+    fn ld_b_vx(&mut self, vx: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
+        self.check_range(self.i, 3)?;
 
         let val = self.regs[usize::from(vx)];
 
@@ -495,28 +860,811 @@ impl<'a> Cpu<'a> {
         self.ram.set(self.i + 1, (val % 100) / 10);
         self.ram.set(self.i + 2, val % 10);
 
-        self.pc += 2;
+        self.advance(2)
     }
 
-    fn ld_i_vx(&mut self, vx: u8) {
+    fn ld_i_vx(&mut self, vx: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
-        assert!(self.i + (vx as u16) < 4095);
+        self.check_range(self.i, u16::from(vx) + 1)?;
 
         for i in 0..=vx {
             self.ram.set(self.i + i as u16, self.regs[usize::from(i)]);
         }
 
-        self.pc += 2;
+        if self.quirks.load_store_increments_i {
+            self.i = self.i.wrapping_add(u16::from(vx) + 1);
+        }
+
+        self.advance(2)
     }
 
-    fn ld_vx_i(&mut self, vx: u8) {
+    fn ld_vx_i(&mut self, vx: u8) -> Result<(), EmuError> {
         assert!(vx < 16);
-        assert!(self.i + (vx as u16) < 4095);
+        self.check_range(self.i, u16::from(vx) + 1)?;
 
         for i in 0..=vx {
             self.regs[usize::from(i)] = self.ram.read(self.i + (i as u16));
         }
 
-        self.pc += 2;
+        if self.quirks.load_store_increments_i {
+            self.i = self.i.wrapping_add(u16::from(vx) + 1);
+        }
+
+        self.advance(2)
+    }
+
+    fn scd_nibble(&mut self, nibble: u8) -> Result<(), EmuError> {
+        self.display.scroll_down(nibble, self.planes);
+        self.advance(2)
+    }
+
+    fn scr(&mut self) -> Result<(), EmuError> {
+        self.display.scroll_right(4, self.planes);
+        self.advance(2)
+    }
+
+    fn scl(&mut self) -> Result<(), EmuError> {
+        self.display.scroll_left(4, self.planes);
+        self.advance(2)
+    }
+
+    fn set_hires(&mut self, hires: bool) -> Result<(), EmuError> {
+        self.display.set_hires(hires);
+        self.advance(2)
+    }
+
+    fn ld_hf_vx(&mut self, vx: u8) -> Result<(), EmuError> {
+        assert!(vx < 16);
+
+        self.i = BIG_FONT_ADDR + u16::from(self.regs[usize::from(vx)] & 0xF) * 10;
+
+        self.advance(2)
+    }
+
+    fn ld_r_vx(&mut self, vx: u8) -> Result<(), EmuError> {
+        assert!(vx < 16);
+
+        self.rpl[..=usize::from(vx)].copy_from_slice(&self.regs[..=usize::from(vx)]);
+
+        self.advance(2)
+    }
+
+    fn ld_vx_r(&mut self, vx: u8) -> Result<(), EmuError> {
+        assert!(vx < 16);
+
+        self.regs[..=usize::from(vx)].copy_from_slice(&self.rpl[..=usize::from(vx)]);
+
+        self.advance(2)
+    }
+
+    // 5xy2/5xy3 walk the registers downwards when x > y and leave I alone
+    fn register_range(vx: u8, vy: u8) -> Vec<u8> {
+        assert!(vx < 16);
+        assert!(vy < 16);
+
+        if vx <= vy {
+            (vx..=vy).collect()
+        } else {
+            (vy..=vx).rev().collect()
+        }
+    }
+
+    fn save_vx_vy(&mut self, vx: u8, vy: u8) -> Result<(), EmuError> {
+        let regs = Self::register_range(vx, vy);
+        self.check_range(self.i, regs.len() as u16)?;
+
+        for (offset, &reg) in regs.iter().enumerate() {
+            self.ram.set(self.i + offset as u16, self.regs[usize::from(reg)]);
+        }
+
+        self.advance(2)
+    }
+
+    fn load_vx_vy_i(&mut self, vx: u8, vy: u8) -> Result<(), EmuError> {
+        let regs = Self::register_range(vx, vy);
+        self.check_range(self.i, regs.len() as u16)?;
+
+        for (offset, &reg) in regs.iter().enumerate() {
+            self.regs[usize::from(reg)] = self.ram.read(self.i + offset as u16);
+        }
+
+        self.advance(2)
+    }
+
+    fn ld_i_long(&mut self) -> Result<(), EmuError> {
+        self.check_range(self.pc, 4)?;
+
+        self.i = self.opcode_at(self.pc + 2);
+
+        self.advance(4)
+    }
+
+    fn plane_n(&mut self, planes: u8) -> Result<(), EmuError> {
+        assert!(planes < 1 << PLANE_COUNT);
+
+        self.planes = planes;
+
+        self.advance(2)
+    }
+
+    fn ld_audio_i(&mut self) -> Result<(), EmuError> {
+        self.check_range(self.i, 16)?;
+
+        let mut pattern = [0; 16];
+        for (offset, byte) in pattern.iter_mut().enumerate() {
+            *byte = self.ram.read(self.i + offset as u16);
+        }
+        self.pattern = Some(pattern);
+        self.audio.set_pattern(self.pattern, self.pitch);
+
+        self.advance(2)
+    }
+
+    fn ld_pitch_vx(&mut self, vx: u8) -> Result<(), EmuError> {
+        assert!(vx < 16);
+
+        self.pitch = self.regs[usize::from(vx)];
+        if self.pattern.is_some() {
+            self.audio.set_pattern(self.pattern, self.pitch);
+        }
+
+        self.advance(2)
+    }
+}
+
+// FNV-1a over memory and the pixels of the current display mode
+fn hash_machine(ram: &Ram, display: &dyn Display) -> u64 {
+    let pixels = (0..display.height()).flat_map(|y| (0..display.width()).map(move |x| (x, y)));
+    let bytes = (0..ram.size())
+        .map(|addr| ram.read(addr as u16))
+        .chain(std::iter::once(u8::from(display.hires())))
+        .chain(pixels.map(|(x, y)| display.vram_get(x, y)));
+
+    bytes.fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+// Machines for tests to run programs on, shared with the other modules' tests
+#[cfg(test)]
+pub(crate) mod harness {
+    use std::convert::TryFrom;
+
+    use super::Cpu;
+    use crate::audio::{Audio, NullAudio};
+    use crate::display::HeadlessDisplay;
+    use crate::keypad::ScriptedKeypad;
+    use crate::quirks::QuirkProfile;
+    use crate::ram::Ram;
+
+    pub(crate) type TestCpu<A = NullAudio> = Cpu<HeadlessDisplay, ScriptedKeypad, A>;
+
+    // A `profile` machine, memory size included, with `program` loaded at 0x200
+    pub(crate) fn machine<A: Audio>(program: &[u16], profile: QuirkProfile, audio: A) -> TestCpu<A> {
+        let mut ram = Ram::with_size(profile.memory_size());
+        for (i, &op) in program.iter().enumerate() {
+            let addr = 0x200 + 2 * u16::try_from(i).unwrap();
+            ram.set(addr, (op >> 8) as u8);
+            ram.set(addr + 1, (op & 0xFF) as u8);
+        }
+
+        let mut cpu = Cpu::new(ram, HeadlessDisplay::new(), ScriptedKeypad::new(), audio);
+        cpu.set_quirks(profile.quirks());
+        cpu
+    }
+
+    // Runs `steps` instructions of `program` after `setup` has had a go at the machine
+    pub(crate) fn exec(program: &[u16], profile: QuirkProfile, steps: usize, setup: impl FnOnce(&mut TestCpu)) -> TestCpu {
+        let mut cpu = machine(program, profile, NullAudio);
+        setup(&mut cpu);
+        for _ in 0..steps {
+            cpu.tick().unwrap();
+        }
+
+        cpu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::harness::{exec, machine, TestCpu};
+    use super::*;
+
+    use crate::audio::{NullAudio, RecordingAudio};
+    use crate::quirks::QuirkProfile;
+    use crate::rng::ScriptedRng;
+
+    #[test]
+    fn test_headless_draw() {
+        // LD V0, 0x00; LD V1, 0x00; LD F, V0; DRW V0, V1, 0x5
+        let cpu = exec(&[0x6000, 0x6100, 0xF029, 0xD015], QuirkProfile::Vip, 4, |_| {});
+
+        assert_eq!(cpu.pc, 0x208);
+        assert_eq!(cpu.regs[0xF], 0);
+
+        // the "0" font glyph is 0xF0, 0x90, 0x90, 0x90, 0xF0
+        assert_eq!(cpu.display().vram_get(0, 0), 1);
+        assert_eq!(cpu.display().vram_get(3, 0), 1);
+        assert_eq!(cpu.display().vram_get(1, 1), 0);
+        assert_eq!(cpu.display().vram_get(3, 4), 1);
+        assert_eq!(cpu.display().vram_get(4, 0), 0);
+        assert_eq!(cpu.display().redraws(), 0);
+    }
+
+    #[test]
+    fn test_headless_skp() {
+        // LD V2, 0x05; SKP V2
+        let cpu = exec(&[0x6205, 0xE29E], QuirkProfile::Vip, 2, |cpu| {
+            cpu.keypad_mut().press(0x5);
+            cpu.poll_input().unwrap();
+        });
+
+        assert_eq!(cpu.pc, 0x206);
+    }
+
+    #[test]
+    fn test_keys_polled_once_per_frame() {
+        // SKP V0; JP 0x200; EXIT
+        let mut cpu = exec(&[0xE09E, 0x1200, 0x00FD], QuirkProfile::SuperChip, 1, |cpu| cpu.set_cycles_per_frame(4));
+        cpu.keypad_mut().press(0x0);
+
+        // the press is only seen once the frame is over
+        for _ in 0..3 {
+            assert!(matches!(cpu.tick(), Ok(StepOutcome::Executed(_))));
+        }
+        assert_eq!((cpu.frames(), cpu.keys().bits()), (1, 0x1));
+        cpu.tick().unwrap();
+        assert_eq!(cpu.tick(), Ok(StepOutcome::Exited));
+    }
+
+    #[test]
+    fn test_sound_timer_starts_beeper() {
+        // LD V0, 0x03; LD ST, V0
+        let mut cpu = machine(&[0x6003, 0xF018], QuirkProfile::Vip, RecordingAudio::new());
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+
+        assert_eq!(cpu.audio().transitions().len(), 1);
+        assert_eq!(cpu.audio().transitions()[0].tick, 1);
+        assert!(cpu.audio().is_beeping());
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        // LD V0, 0x00; LD V1, 0x00; LD F, V0; DRW V0, V1, 0x5; CLS
+        let mut cpu = exec(&[0x6000, 0x6100, 0xF029, 0xD015, 0x00E0], QuirkProfile::Vip, 4, |_| {});
+
+        let saved = cpu.save_state();
+        cpu.tick().unwrap();
+        assert_eq!(cpu.display().vram_get(0, 0), 0);
+
+        cpu.load_state(&saved).unwrap();
+        assert_eq!(cpu.pc, 0x208);
+        assert_eq!(cpu.display().vram_get(0, 0), 1);
+        assert_eq!(cpu.snapshot(), SaveState::from_bytes(&saved).unwrap());
+    }
+
+    #[test]
+    fn test_superchip_hires_sprite() {
+        // HIGH; LD V0, 0x78; LD V1, 0x3C; LD I, 0x300; DRW V0, V1, 0x0
+        let cpu = exec(&[0x00FF, 0x6078, 0x613C, 0xA300, 0xD010], QuirkProfile::SuperChip, 5, |cpu| {
+            for addr in 0x300..0x320 {
+                cpu.ram.set(addr, 0xFF);
+            }
+        });
+
+        assert_eq!(cpu.regs[0xF], 0);
+        assert!(cpu.display().hires());
+        assert_eq!(cpu.display().vram_get(120, 60), 1);
+        assert_eq!(cpu.display().vram_get(127, 63), 1);
+        assert_eq!(cpu.display().vram_get(119, 60), 0);
+        assert_eq!(cpu.display().vram_get(0, 0), 0);
+    }
+
+    #[test]
+    fn test_superchip_scroll_font_and_flags() {
+        // LD V0, 0x00; LD V1, 0x00; LD F, V0; DRW V0, V1, 0x1; SCD 0x2; SCR;
+        // LD V2, 0x07; LD HF, V2; LD R, V2; LD V2, 0x00; LD V2, R; EXIT
+        let program = [
+            0x6000, 0x6100, 0xF029, 0xD011, 0x00C2, 0x00FB,
+            0x6207, 0xF230, 0xF275, 0x6200, 0xF285, 0x00FD,
+        ];
+
+        let mut cpu = machine(&program, QuirkProfile::SuperChip, NullAudio);
+        for _ in 0..11 {
+            assert!(matches!(cpu.tick().unwrap(), StepOutcome::Executed(_)));
+        }
+        assert_eq!(cpu.tick().unwrap(), StepOutcome::Exited);
+        assert_eq!(cpu.pc, 0x216);
+
+        assert_eq!(cpu.i, BIG_FONT_ADDR + 70);
+        assert_eq!(cpu.regs[0x2], 0x07);
+        assert_eq!(cpu.rpl[0x2], 0x07);
+
+        // the top row of the "0" glyph moved down two rows and right four columns
+        assert_eq!(cpu.display().vram_get(0, 0), 0);
+        assert_eq!(cpu.display().vram_get(3, 2), 0);
+        assert_eq!(cpu.display().vram_get(4, 2), 1);
+        assert_eq!(cpu.display().vram_get(7, 2), 1);
+        assert_eq!(cpu.display().vram_get(8, 2), 0);
+    }
+
+    #[test]
+    fn test_xochip_memory_and_registers() {
+        // LD V1, 0x11; LD V2, 0x22; LD V3, 0x33; LD I, LONG 0xFF00; SAVE V3, V1;
+        // SE V0, 0x00; LD I, LONG 0x0000; LOAD V5, V7
+        let program = [0x6111, 0x6222, 0x6333, 0xF000, 0xFF00, 0x5312, 0x3000, 0xF000, 0x0000, 0x5573];
+        let cpu = exec(&program, QuirkProfile::XoChip, 7, |_| {});
+
+        assert_eq!(cpu.pc, 0x214);
+        assert_eq!(cpu.i, 0xFF00);
+        assert_eq!((cpu.peek(0xFF00), cpu.peek(0xFF01), cpu.peek(0xFF02)), (0x33, 0x22, 0x11));
+        assert_eq!(&cpu.regs[5..8], &[0x33, 0x22, 0x11]);
+        assert_eq!(cpu.snapshot().ram.len(), 0x10000);
+    }
+
+    #[test]
+    fn test_skip_over_f000() {
+        // SE V0, 0x00; (F000 0x1234 on XO-CHIP); LD V1, 0x01
+        let program = [0x3000, 0xF000, 0x1234, 0x6101];
+
+        // XO-CHIP steps over both words of the long load
+        let cpu = exec(&program, QuirkProfile::XoChip, 2, |_| {});
+        assert_eq!((cpu.pc, cpu.regs[1], cpu.i), (0x208, 0x01, 0));
+
+        // everywhere else F000 is a word on its own, so the skip lands on 0x1234
+        for &profile in &[QuirkProfile::Vip, QuirkProfile::Chip48, QuirkProfile::SuperChip] {
+            assert_eq!(exec(&program, profile, 1, |_| {}).pc, 0x204, "{}", profile);
+        }
+    }
+
+    #[test]
+    fn test_xochip_planes_and_audio() {
+        // PLANE 3; LD I, 0x300; DRW V0, V0, 0x1; PLANE 2; CLS; LD AUDIO, [I];
+        // LD V0, 0x70; LD PITCH, V0
+        let program = [0xF301, 0xA300, 0xD001, 0xF201, 0x00E0, 0xF002, 0x6070, 0xF03A];
+        let mut cpu = machine(&program, QuirkProfile::XoChip, RecordingAudio::new());
+        cpu.ram.set(0x300, 0b1100_0000);
+        cpu.ram.set(0x301, 0b1010_0000);
+        let before_audio = cpu.save_state();
+        for _ in 0..3 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!((cpu.display().vram_get(0, 0), cpu.display().vram_get(1, 0), cpu.display().vram_get(2, 0)), (3, 1, 2));
+
+        for _ in 0..5 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!((cpu.display().vram_get(0, 0), cpu.display().vram_get(1, 0), cpu.display().vram_get(2, 0)), (1, 1, 0));
+
+        let mut pattern = [0; 16];
+        pattern[..2].copy_from_slice(&[0b1100_0000, 0b1010_0000]);
+        assert_eq!(cpu.audio().pattern(), Some((pattern, 0x70)));
+
+        // going back to before LD AUDIO, [I] brings back the plain tone
+        cpu.load_state(&before_audio).unwrap();
+        assert_eq!(cpu.audio().pattern(), None);
+    }
+
+    #[test]
+    fn test_timers_follow_emulated_frames() {
+        // LD V0, 0x0A; LD DT, V0; JP 0x204
+        let mut cpu = exec(&[0x600A, 0xF015, 0x1204], QuirkProfile::Vip, 4, |cpu| cpu.set_cycles_per_frame(4));
+        assert_eq!((cpu.dt, cpu.frames()), (9, 1));
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        for _ in 0..11 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!((cpu.dt, cpu.frames(), cpu.cycles()), (7, 3, 15));
+    }
+
+    #[test]
+    fn test_rewind() {
+        // LD V0, 0x00; ADD V0, 0x01; RND V1, 0xFF; JP 0x202
+        let mut cpu = exec(&[0x6000, 0x7001, 0xC1FF, 0x1202], QuirkProfile::Vip, 0, |cpu| cpu.set_cycles_per_frame(4));
+        assert_eq!(cpu.rewind(1), 0);
+
+        cpu.set_rewind_budget(1 << 20);
+        let mut states = Vec::new();
+        for _ in 0..10 {
+            for _ in 0..4 {
+                cpu.tick().unwrap();
+            }
+            states.push(cpu.save_state());
+        }
+
+        assert_eq!(cpu.rewind_frames(), 9);
+        assert_eq!(cpu.rewind(3), 3);
+        assert_eq!(cpu.frames(), 7);
+        assert_eq!(cpu.save_state(), states[6]);
+
+        // replaying from there, random numbers included, ends up in the same place
+        for _ in 0..12 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!(cpu.save_state(), states[9]);
+
+        assert_eq!(cpu.rewind(100), 9);
+        assert_eq!(cpu.save_state(), states[0]);
+    }
+
+    #[test]
+    fn test_runs_are_reproducible() {
+        let run = || {
+            // LD V0, 0x03; LD DT, V0; LD V1, DT; RND V2, 0xFF; SE V1, 0x00; JP 0x204; LD ST, V2; JP 0x20E
+            let program = [0x6003, 0xF015, 0xF107, 0xC2FF, 0x3100, 0x1204, 0xF218, 0x120E];
+            exec(&program, QuirkProfile::Vip, 100, |cpu| cpu.set_rng(Box::new(SeededRng::new(1)))).save_state()
+        };
+
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn test_scripted_rng() {
+        // RND V0, 0xFF; RND V1, 0x0F; RND V2, 0xFF
+        let cpu = exec(&[0xC0FF, 0xC10F, 0xC2FF], QuirkProfile::Vip, 3, |cpu| {
+            cpu.set_rng(Box::new(ScriptedRng::new(vec![0x12, 0x34])))
+        });
+
+        assert_eq!(&cpu.regs[..3], &[0x12, 0x04, 0x12]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let run_until_error = |program: &[u16], quit: bool| {
+            let mut cpu = exec(program, QuirkProfile::Vip, 0, |cpu| {
+                if quit {
+                    cpu.keypad_mut().request_quit();
+                }
+            });
+            (0..100).find_map(|_| cpu.tick().err()).expect("program ran without an error")
+        };
+
+        // LD V0, 0x01; (invalid)
+        assert_eq!(run_until_error(&[0x6001, 0x5121], false), EmuError::InvalidOpcode { pc: 0x202, opcode: 0x5121 });
+
+        // RET
+        assert_eq!(run_until_error(&[0x00EE], false), EmuError::StackUnderflow { pc: 0x200 });
+
+        // CALL 0x200
+        assert_eq!(run_until_error(&[0x2200], false), EmuError::StackOverflow { pc: 0x200 });
+
+        // LD I, 0xFFE; LD [I], V2
+        assert_eq!(run_until_error(&[0xAFFE, 0xF255], false), EmuError::MemoryOutOfBounds { pc: 0x202, addr: 0x1000 });
+
+        // JP 0xFFF
+        assert_eq!(run_until_error(&[0x1FFF], false), EmuError::MemoryOutOfBounds { pc: 0xFFF, addr: 0x1000 });
+
+        // LD V3, 0x10; SKP V3
+        assert_eq!(run_until_error(&[0x6310, 0xE39E], false), EmuError::InvalidKey { pc: 0x202, key: 0x10 });
+
+        // LD V0, K
+        assert_eq!(run_until_error(&[0xF00A], true), EmuError::UserQuit);
+
+        // JP 0x200
+        assert_eq!(run_until_error(&[0x1200], true), EmuError::UserQuit);
+    }
+
+    #[test]
+    fn test_top_of_memory() {
+        // an XO-CHIP machine about to run `opcode`, the last two bytes of which sit at `pc`
+        let at = |pc: u16, opcode: u32| {
+            let mut cpu = machine(&[], QuirkProfile::XoChip, NullAudio);
+            let len = if opcode > 0xFFFF { 4 } else { 2 };
+            for (offset, byte) in opcode.to_be_bytes()[4 - len..].iter().enumerate() {
+                cpu.ram.set(pc + offset as u16, *byte);
+            }
+            cpu.pc = pc;
+            cpu
+        };
+        let past_the_end = |pc: u16, len: u32| EmuError::MemoryOutOfBounds { pc, addr: u32::from(pc) + len };
+
+        // ADD I, V0
+        let mut cpu = at(0x200, 0xF01E);
+        cpu.i = 0xFFFE;
+        cpu.regs[0] = 0x03;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.i, 0x0001);
+
+        // LD [I], V1 from 0xFFFE leaves I one past the end of memory, wrapped to 0
+        let mut cpu = at(0x200, 0xF155);
+        cpu.i = 0xFFFE;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.i, 0x0000);
+
+        // LD V0, 0x00; SE V0, 0x00; SNE V0, 0x00; CALL 0x200
+        assert_eq!(at(0xFFFE, 0x6000).tick(), Err(past_the_end(0xFFFE, 2)));
+        assert_eq!(at(0xFFFC, 0x3000).tick(), Err(past_the_end(0xFFFC, 4)));
+        assert_eq!(at(0xFFFE, 0x4000).tick(), Err(past_the_end(0xFFFE, 2)));
+        assert_eq!(at(0xFFFE, 0x2200).tick(), Err(past_the_end(0xFFFE, 2)));
+
+        // LD I, LONG 0x1234, with and without its second word in memory
+        let mut cpu = at(0xFFFC, 0xF000_1234);
+        assert_eq!(cpu.tick(), Err(past_the_end(0xFFFC, 4)));
+        assert_eq!(cpu.i, 0x1234);
+        assert_eq!(at(0xFFFE, 0xF000).tick(), Err(EmuError::MemoryOutOfBounds { pc: 0xFFFE, addr: 0x10001 }));
+    }
+
+    #[test]
+    fn test_op_jumps_and_calls() {
+        // CALL 0x206; JP 0x20A; (pad); RET; (pad); LD V0, 0x04; JP V0, 0x20E
+        let cpu = exec(&[0x2206, 0x120A, 0x0000, 0x00EE, 0x0000, 0x6004, 0xB20E], QuirkProfile::Vip, 2, |_| {});
+        assert_eq!((cpu.pc, cpu.sp), (0x202, 0));
+        assert_eq!(cpu.stack[0], 0x202);
+
+        let cpu = exec(&[0x2206, 0x120A, 0x0000, 0x00EE, 0x0000, 0x6004, 0xB20E], QuirkProfile::Vip, 5, |_| {});
+        assert_eq!(cpu.pc, 0x212);
+    }
+
+    #[test]
+    fn test_op_skips() {
+        let skips = |opcode: u16, v0: u8, v1: u8| {
+            exec(&[opcode], QuirkProfile::Vip, 1, |cpu| {
+                cpu.regs[0] = v0;
+                cpu.regs[1] = v1;
+            })
+            .pc
+                == 0x204
+        };
+
+        // SE V0, 0x12
+        assert!(skips(0x3012, 0x12, 0));
+        assert!(!skips(0x3012, 0x13, 0));
+        // SNE V0, 0x12
+        assert!(skips(0x4012, 0x13, 0));
+        assert!(!skips(0x4012, 0x12, 0));
+        // SE V0, V1
+        assert!(skips(0x5010, 7, 7));
+        assert!(!skips(0x5010, 7, 8));
+        // SNE V0, V1
+        assert!(skips(0x9010, 7, 8));
+        assert!(!skips(0x9010, 7, 7));
+    }
+
+    #[test]
+    fn test_op_loads() {
+        // LD V3, 0x42; ADD V3, 0xC0; LD V4, V3; LD I, 0x123
+        let cpu = exec(&[0x6342, 0x73C0, 0x8430, 0xA123], QuirkProfile::Vip, 4, |cpu| cpu.regs[0xF] = 0xAA);
+        assert_eq!((cpu.regs[3], cpu.regs[4], cpu.i), (0x02, 0x02, 0x123));
+        // 7xkk never touches the carry flag
+        assert_eq!(cpu.regs[0xF], 0xAA);
+
+        // LD DT, V0; LD ST, V0; LD V1, DT; ADD I, V0
+        let cpu = exec(&[0xF015, 0xF018, 0xF107, 0xF01E], QuirkProfile::Vip, 4, |cpu| {
+            cpu.regs[0] = 0x20;
+            cpu.i = 0x300;
+        });
+        assert_eq!((cpu.dt, cpu.st, cpu.regs[1], cpu.i), (0x20, 0x20, 0x20, 0x320));
+
+        // RND V0, 0x0F
+        let cpu = exec(&[0xC00F], QuirkProfile::Vip, 1, |cpu| cpu.set_rng(Box::new(ScriptedRng::new(vec![0xA5]))));
+        assert_eq!(cpu.regs[0], 0x05);
+    }
+
+    #[test]
+    fn test_op_arithmetic() {
+        // (opcode, V0, V1, expected V0, expected VF), with VF starting out as 0xCC
+        let cases: [(u16, u8, u8, u8, u8); 15] = [
+            (0x8011, 0x0F, 0xF0, 0xFF, 0x00), // OR, VF reset
+            (0x8012, 0x0F, 0x3C, 0x0C, 0x00), // AND
+            (0x8013, 0x0F, 0x3C, 0x33, 0x00), // XOR
+            (0x8014, 0xF0, 0x20, 0x10, 0x01), // ADD with carry
+            (0x8014, 0x10, 0x20, 0x30, 0x00),
+            (0x8015, 0x30, 0x10, 0x20, 0x01), // SUB, VF is NOT borrow
+            (0x8015, 0x10, 0x30, 0xE0, 0x00),
+            (0x8015, 0x10, 0x10, 0x00, 0x01),
+            (0x8017, 0x10, 0x30, 0x20, 0x01), // SUBN
+            (0x8017, 0x30, 0x10, 0xE0, 0x00),
+            (0x8016, 0x00, 0x05, 0x02, 0x01), // SHR shifts Vy on the VIP
+            (0x8016, 0x00, 0x04, 0x02, 0x00),
+            (0x801E, 0x00, 0x81, 0x02, 0x01), // SHL
+            (0x801E, 0x00, 0x41, 0x82, 0x00),
+            (0x8010, 0x00, 0x99, 0x99, 0xCC), // LD leaves VF alone
+        ];
+
+        for &(opcode, v0, v1, result, flag) in cases.iter() {
+            let cpu = exec(&[opcode], QuirkProfile::Vip, 1, |cpu| {
+                cpu.regs[0] = v0;
+                cpu.regs[1] = v1;
+                cpu.regs[0xF] = 0xCC;
+            });
+            assert_eq!((cpu.regs[0], cpu.regs[0xF]), (result, flag), "opcode 0x{:04x}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_op_flag_written_last() {
+        // with VF as the destination the flag wins over the result
+        for &(opcode, vf, v1, flag) in [(0x8F14, 0xFF, 0x01, 1), (0x8F15, 0x01, 0x02, 0), (0x8F17, 0x02, 0x01, 0), (0x8F1E, 0, 0x80, 1), (0x8F16, 0, 0x03, 1)].iter() {
+            let cpu = exec(&[opcode], QuirkProfile::Vip, 1, |cpu| {
+                cpu.regs[0xF] = vf;
+                cpu.regs[1] = v1;
+            });
+            assert_eq!(cpu.regs[0xF], flag, "opcode 0x{:04x}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_op_memory() {
+        // LD B, V0; LD F, V1; LD F, V2
+        let cpu = exec(&[0xF033, 0xF129, 0xF229], QuirkProfile::Vip, 1, |cpu| {
+            cpu.regs[0] = 254;
+            cpu.i = 0x300;
+        });
+        assert_eq!((cpu.peek(0x300), cpu.peek(0x301), cpu.peek(0x302)), (2, 5, 4));
+
+        // the font lookup uses the low nibble, and 0xFF * 5 doesn't fit in a byte
+        let cpu = exec(&[0xF033, 0xF129], QuirkProfile::Vip, 2, |cpu| cpu.regs[1] = 0xFF);
+        assert_eq!(cpu.i, 0xF * 5);
+        let cpu = exec(&[0xF033, 0xF129, 0xF229], QuirkProfile::Vip, 3, |cpu| cpu.regs[2] = 0x3A);
+        assert_eq!(cpu.i, 0xA * 5);
+
+        // LD [I], V2; LD I, 0x300; LD V5, [I]
+        let cpu = exec(&[0xF255, 0xA300, 0xF565], QuirkProfile::Vip, 3, |cpu| {
+            cpu.regs[..3].copy_from_slice(&[1, 2, 3]);
+            cpu.i = 0x300;
+        });
+        assert_eq!(&cpu.regs[..6], &[1, 2, 3, 0, 0, 0]);
+        assert_eq!(cpu.i, 0x306);
+        assert_eq!(cpu.peek(0x303), 0);
+    }
+
+    #[test]
+    fn test_op_draw() {
+        // LD I, 0x300; DRW V0, V1, 2; DRW V0, V1, 1; CLS
+        let program = [0xA300, 0xD012, 0xD011, 0x00E0];
+        let setup = |cpu: &mut TestCpu| {
+            cpu.set_cycles_per_frame(1);
+            cpu.ram.set(0x300, 0b1000_0001);
+            cpu.ram.set(0x301, 0b0100_0000);
+            cpu.regs[0] = 60;
+            cpu.regs[1] = 31;
+        };
+
+        // the sprite is clipped at the bottom right edge
+        let cpu = exec(&program, QuirkProfile::Vip, 2, setup);
+        assert_eq!(cpu.regs[0xF], 0);
+        assert_eq!((cpu.display().vram_get(60, 31), cpu.display().vram_get(63, 31)), (1, 0));
+        assert_eq!((cpu.display().vram_get(3, 31), cpu.display().vram_get(61, 0)), (0, 0));
+
+        // drawing over lit pixels erases them and sets VF
+        let cpu = exec(&program, QuirkProfile::Vip, 3, setup);
+        assert_eq!(cpu.regs[0xF], 1);
+        assert_eq!(cpu.display().vram_get(60, 31), 0);
+
+        let cpu = exec(&program, QuirkProfile::Vip, 4, |cpu| {
+            setup(cpu);
+            cpu.regs[0] = 0;
+        });
+        assert!(cpu.display().to_ascii().chars().all(|c| c == '.' || c == '\n'));
+    }
+
+    #[test]
+    fn test_op_keys() {
+        // LD V0, 0x0A; SKP V0; (pad); SKNP V0; LD V1, K
+        let mut cpu = exec(&[0x600A, 0xE09E, 0x0000, 0xE0A1, 0xF10A], QuirkProfile::Vip, 3, |cpu| {
+            cpu.keypad_mut().press(0xA);
+            cpu.poll_input().unwrap();
+        });
+        assert_eq!(cpu.pc, 0x208);
+
+        cpu.keypad_mut().release(0xA);
+        cpu.poll_input().unwrap();
+        cpu.pc = 0x206;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x20A);
+    }
+
+    #[test]
+    fn test_key_wait() {
+        // LD V1, K
+        let mut cpu = exec(&[0xF10A], QuirkProfile::Vip, 0, |cpu| {
+            cpu.set_cycles_per_frame(2);
+            cpu.dt = 10;
+            cpu.keypad_mut().press(0xA);
+            cpu.poll_input().unwrap();
+        });
+        let step = |cpu: &mut TestCpu| cpu.tick().unwrap();
+        let set_key = |cpu: &mut TestCpu, key, pressed| {
+            if pressed {
+                cpu.keypad_mut().press(key);
+            } else {
+                cpu.keypad_mut().release(key);
+            }
+            cpu.poll_input().unwrap();
+        };
+
+        // a key held since before the wait doesn't count, even once released
+        assert_eq!(step(&mut cpu), StepOutcome::WaitingForKey);
+        set_key(&mut cpu, 0xA, false);
+        assert_eq!(step(&mut cpu), StepOutcome::WaitingForKey);
+
+        // a new press only counts once released, and timers keep running
+        set_key(&mut cpu, 0x3, true);
+        assert_eq!(step(&mut cpu), StepOutcome::WaitingForKey);
+        set_key(&mut cpu, 0x5, true);
+        assert_eq!(step(&mut cpu), StepOutcome::WaitingForKey);
+        set_key(&mut cpu, 0x5, false);
+        assert_eq!(step(&mut cpu), StepOutcome::WaitingForKey);
+        assert_eq!((cpu.pc, cpu.dt, cpu.cycles), (0x200, 8, 5));
+
+        // a save state taken mid-wait resumes it
+        let state = cpu.save_state();
+        set_key(&mut cpu, 0x3, false);
+        assert_eq!(step(&mut cpu), StepOutcome::Executed(Instruction::LdVxK(1)));
+        assert_eq!((cpu.pc, cpu.regs[1]), (0x202, 0x3));
+
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.pc, 0x200);
+        assert!(matches!(step(&mut cpu), StepOutcome::Executed(_)));
+        assert_eq!(cpu.regs[1], 0x3);
+    }
+
+    #[test]
+    fn test_quirk_shift() {
+        // LD V1, 0x03; LD V2, 0x10; SHR V1, V2
+        let program = [0x6103, 0x6210, 0x8126];
+
+        let vip = exec(&program, QuirkProfile::Vip, program.len(), |_| {});
+        assert_eq!((vip.regs[1], vip.regs[0xF]), (0x08, 0));
+
+        let chip48 = exec(&program, QuirkProfile::Chip48, program.len(), |_| {});
+        assert_eq!((chip48.regs[1], chip48.regs[0xF]), (0x01, 1));
+    }
+
+    #[test]
+    fn test_quirk_load_store() {
+        // LD I, 0x300; LD [I], V2
+        let program = [0xA300, 0xF255];
+
+        assert_eq!(exec(&program, QuirkProfile::Vip, program.len(), |_| {}).i, 0x303);
+        assert_eq!(exec(&program, QuirkProfile::Chip48, program.len(), |_| {}).i, 0x300);
+    }
+
+    #[test]
+    fn test_quirk_jump() {
+        // LD V0, 0x04; LD V2, 0x08; JP V0, 0x210
+        let program = [0x6004, 0x6208, 0xB210];
+
+        assert_eq!(exec(&program, QuirkProfile::Vip, program.len(), |_| {}).pc, 0x214);
+        assert_eq!(exec(&program, QuirkProfile::SuperChip, program.len(), |_| {}).pc, 0x218);
+    }
+
+    #[test]
+    fn test_quirk_clipping() {
+        // LD V0, 0x3E; LD V1, 0x00; LD F, V1; DRW V0, V1, 0x5
+        let program = [0x603E, 0x6100, 0xF129, 0xD015];
+
+        let clipped = exec(&program, QuirkProfile::Vip, program.len(), |_| {});
+        assert_eq!(clipped.display().vram_get(63, 0), 1);
+        assert_eq!(clipped.display().vram_get(0, 0), 0);
+
+        let wrapped = exec(&program, QuirkProfile::Vip, program.len(), |cpu| {
+            cpu.set_quirks(Quirks { clip_sprites: false, ..Quirks::vip() })
+        });
+        assert_eq!(wrapped.display().vram_get(63, 0), 1);
+        assert_eq!(wrapped.display().vram_get(1, 0), 1);
+        assert_eq!(wrapped.display().vram_get(2, 0), 0);
+    }
+
+    #[test]
+    fn test_quirk_vf_reset() {
+        // LD VF, 0x05; LD V1, 0x01; OR V1, V1
+        let program = [0x6F05, 0x6101, 0x8111];
+
+        assert_eq!(exec(&program, QuirkProfile::Vip, program.len(), |_| {}).regs[0xF], 0);
+        assert_eq!(exec(&program, QuirkProfile::Chip48, program.len(), |_| {}).regs[0xF], 5);
+    }
+
+    #[test]
+    fn test_quirk_instruction_set() {
+        // HIGH, then XO-CHIP's PLANE 2
+        let run = |program: &[u16], profile: QuirkProfile| exec(program, profile, 0, |_| {}).tick();
+
+        assert_eq!(run(&[0x00FF], QuirkProfile::Vip), Err(EmuError::InvalidOpcode { pc: 0x200, opcode: 0x00FF }));
+        assert_eq!(run(&[0x00FF], QuirkProfile::Chip48), Err(EmuError::InvalidOpcode { pc: 0x200, opcode: 0x00FF }));
+        assert!(run(&[0x00FF], QuirkProfile::SuperChip).is_ok());
+
+        assert_eq!(run(&[0xF201], QuirkProfile::SuperChip), Err(EmuError::InvalidOpcode { pc: 0x200, opcode: 0xF201 }));
+        assert!(run(&[0xF201], QuirkProfile::XoChip).is_ok());
     }
 }
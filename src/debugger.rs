@@ -0,0 +1,312 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::Write;
+
+use crate::audio::Audio;
+use crate::cpu::Cpu;
+use crate::display::Display;
+use crate::error::EmuError;
+use crate::instruction::{decode, Instruction};
+use crate::keypad::Keypad;
+
+pub const HELP: &str = "\
+Commands:
+    s, step [N]         Execute N instructions (default: 1)
+    n, next             Execute one instruction, running through CALLs
+    c, continue         Run until a breakpoint or watchpoint is hit
+    b, break ADDR       Set a breakpoint at ADDR
+    w, watch ADDR       Stop when the byte at ADDR changes
+    d, delete ADDR      Remove the breakpoint or watchpoint at ADDR
+    l, list             List breakpoints and watchpoints
+    r, regs             Dump registers, I, SP, the stack and timers
+    x, mem ADDR [LEN]   Dump LEN bytes of memory from ADDR (default: 16)
+    h, help             Print this message
+    q, quit             Exit the emulator
+Addresses are hexadecimal, with or without a 0x prefix.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Step(u32),
+    Next,
+    Continue,
+    Break(u16),
+    Watch(u16),
+    Delete(u16),
+    List,
+    Registers,
+    Memory { addr: u16, len: u16 },
+    Help,
+    Quit,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    Empty,
+    UnknownCommand(String),
+    MissingArgument(&'static str),
+    InvalidArgument(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty command"),
+            ParseError::UnknownCommand(cmd) => write!(f, "unknown command '{}', try 'help'", cmd),
+            ParseError::MissingArgument(arg) => write!(f, "missing argument {}", arg),
+            ParseError::InvalidArgument(arg) => write!(f, "invalid argument '{}'", arg),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn parse_addr(arg: Option<&str>) -> Result<u16, ParseError> {
+    let arg = arg.ok_or(ParseError::MissingArgument("ADDR"))?;
+    let digits = arg.trim_start_matches("0x").trim_start_matches("0X");
+
+    match u16::from_str_radix(digits, 16) {
+        Ok(addr) => Ok(addr),
+        Err(_) => Err(ParseError::InvalidArgument(arg.to_string())),
+    }
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, ParseError> {
+        let mut words = line.split_whitespace();
+        let command = words.next().ok_or(ParseError::Empty)?;
+
+        let res = match command {
+            "s" | "step" => match words.next() {
+                Some(count) => Command::Step(count.parse().map_err(|_| ParseError::InvalidArgument(count.to_string()))?),
+                None => Command::Step(1),
+            },
+            "n" | "next" => Command::Next,
+            "c" | "continue" => Command::Continue,
+            "b" | "break" => Command::Break(parse_addr(words.next())?),
+            "w" | "watch" => Command::Watch(parse_addr(words.next())?),
+            "d" | "delete" => Command::Delete(parse_addr(words.next())?),
+            "l" | "list" => Command::List,
+            "r" | "regs" => Command::Registers,
+            "x" | "mem" => {
+                let addr = parse_addr(words.next())?;
+                let len = match words.next() {
+                    Some(len) => len.parse().map_err(|_| ParseError::InvalidArgument(len.to_string()))?,
+                    None => 16,
+                };
+                Command::Memory { addr, len }
+            }
+            "h" | "help" | "?" => Command::Help,
+            "q" | "quit" => Command::Quit,
+            _ => return Err(ParseError::UnknownCommand(command.to_string())),
+        };
+
+        match words.next() {
+            Some(extra) => Err(ParseError::InvalidArgument(extra.to_string())),
+            None => Ok(res),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint(u16),
+    Watchpoint { addr: u16, old: u8, new: u8 },
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint(addr) => write!(f, "breakpoint at 0x{:04x}", addr),
+            StopReason::Watchpoint { addr, old, new } => {
+                write!(f, "watchpoint at 0x{:04x}: 0x{:02x} -> 0x{:02x}", addr, old, new)
+            }
+        }
+    }
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeMap<u16, u8>, // address -> last seen value
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn add_watchpoint<D: Display, K: Keypad, A: Audio>(&mut self, cpu: &Cpu<D, K, A>, addr: u16) {
+        self.watchpoints.insert(addr, cpu.peek(addr));
+    }
+
+    pub fn remove(&mut self, addr: u16) -> bool {
+        let removed_breakpoint = self.breakpoints.remove(&addr);
+        let removed_watchpoint = self.watchpoints.remove(&addr).is_some();
+
+        removed_breakpoint || removed_watchpoint
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.watchpoints.keys().copied()
+    }
+
+    // Should be called after every executed instruction. Watchpoints are
+    // updated to the current memory contents whether or not we stop.
+    pub fn check<D: Display, K: Keypad, A: Audio>(&mut self, cpu: &Cpu<D, K, A>) -> Option<StopReason> {
+        let mut res = None;
+
+        for (&addr, last) in self.watchpoints.iter_mut() {
+            let new = cpu.peek(addr);
+            if new != *last {
+                if res.is_none() {
+                    res = Some(StopReason::Watchpoint { addr, old: *last, new });
+                }
+                *last = new;
+            }
+        }
+
+        if res.is_none() && self.breakpoints.contains(&cpu.pc()) {
+            res = Some(StopReason::Breakpoint(cpu.pc()));
+        }
+
+        res
+    }
+
+    // Returns the (pc, sp) pair to run until when stepping over the
+    // instruction at the current pc, or None if it isn't a CALL. A CALL in
+    // the last word of memory has nowhere to return to, so it's None as well
+    // and stepping into it reports the error.
+    pub fn step_over_target<D: Display, K: Keypad, A: Audio>(cpu: &Cpu<D, K, A>) -> Result<Option<(u16, u8)>, EmuError> {
+        let pc = cpu.pc();
+        if usize::from(pc) + 2 > cpu.memory_size() {
+            return Err(EmuError::MemoryOutOfBounds { pc, addr: u32::from(pc) + 1 });
+        }
+
+        if let Ok(Instruction::CallAddr(_)) = decode(cpu.opcode_at(pc)) {
+            Ok(pc.checked_add(2).map(|ret| (ret, cpu.sp())))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn format_registers<D: Display, K: Keypad, A: Audio>(cpu: &Cpu<D, K, A>) -> String {
+        let mut res = String::new();
+
+        for (i, reg) in cpu.regs().iter().enumerate() {
+            write!(res, "V{:01x}=0x{:02x}", i, reg).unwrap();
+            res.push(if i % 8 == 7 { '\n' } else { ' ' });
+        }
+
+        writeln!(res, "PC=0x{:04x} I=0x{:04x} SP=0x{:02x} DT=0x{:02x} ST=0x{:02x}", cpu.pc(), cpu.i(), cpu.sp(), cpu.dt(), cpu.st()).unwrap();
+
+        res.push_str("Stack:");
+        if cpu.stack().is_empty() {
+            res.push_str(" (empty)");
+        }
+        for addr in cpu.stack().iter().rev() {
+            write!(res, " 0x{:04x}", addr).unwrap();
+        }
+
+        res
+    }
+
+    pub fn format_memory<D: Display, K: Keypad, A: Audio>(cpu: &Cpu<D, K, A>, addr: u16, len: u16) -> String {
+        let end = std::cmp::min(usize::from(addr) + usize::from(len), cpu.memory_size());
+        let mut res = String::new();
+
+        for row in (usize::from(addr)..end).step_by(8) {
+            write!(res, "0x{:04x}:", row).unwrap();
+            for addr in row..std::cmp::min(row + 8, end) {
+                write!(res, " {:02x}", cpu.peek(addr as u16)).unwrap();
+            }
+            res.push('\n');
+        }
+
+        res.pop();
+        res
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cpu::harness::{exec, TestCpu};
+    use crate::quirks::QuirkProfile;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse("s"), Ok(Command::Step(1)));
+        assert_eq!(Command::parse("step 10"), Ok(Command::Step(10)));
+        assert_eq!(Command::parse("b 0x2a0"), Ok(Command::Break(0x2A0)));
+        assert_eq!(Command::parse("  watch 3FF "), Ok(Command::Watch(0x3FF)));
+        assert_eq!(Command::parse("x 200 4"), Ok(Command::Memory { addr: 0x200, len: 4 }));
+        assert_eq!(Command::parse(""), Err(ParseError::Empty));
+        assert_eq!(Command::parse("b"), Err(ParseError::MissingArgument("ADDR")));
+        assert_eq!(Command::parse("b 0x10000"), Err(ParseError::InvalidArgument("0x10000".to_string())));
+        assert_eq!(Command::parse("c now"), Err(ParseError::InvalidArgument("now".to_string())));
+        assert_eq!(Command::parse("jump"), Err(ParseError::UnknownCommand("jump".to_string())));
+    }
+
+    #[test]
+    fn test_breakpoint_and_watchpoint() {
+        // LD V0, 0x07; LD I, 0x300; LD [I], V0; JP 0x200
+        let mut cpu = exec(&[0x6007, 0xA300, 0xF055, 0x1200], QuirkProfile::Vip, 0, |_| {});
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x204);
+        debugger.add_watchpoint(&cpu, 0x300);
+
+        let step = |cpu: &mut TestCpu, debugger: &mut Debugger| {
+            cpu.tick().unwrap();
+            debugger.check(cpu)
+        };
+
+        assert_eq!(step(&mut cpu, &mut debugger), None);
+        assert_eq!(step(&mut cpu, &mut debugger), Some(StopReason::Breakpoint(0x204)));
+        assert_eq!(step(&mut cpu, &mut debugger), Some(StopReason::Watchpoint { addr: 0x300, old: 0x00, new: 0x07 }));
+
+        assert!(debugger.remove(0x204));
+        assert!(!debugger.remove(0x204));
+        assert_eq!(step(&mut cpu, &mut debugger), None);
+        assert_eq!(cpu.pc(), 0x200);
+    }
+
+    #[test]
+    fn test_step_over_target() {
+        // CALL 0x206; LD V1, 0x01; (pad); RET
+        let mut cpu = exec(&[0x2206, 0x6101, 0x0000, 0x00EE], QuirkProfile::Vip, 0, |_| {});
+        assert_eq!(Debugger::step_over_target(&cpu), Ok(Some((0x202, 0))));
+
+        cpu.tick().unwrap();
+        assert_eq!(Debugger::step_over_target(&cpu), Ok(None));
+        assert_eq!(cpu.stack(), &[0x202]);
+        assert!(Debugger::format_registers(&cpu).ends_with("Stack: 0x0202"));
+
+        // JP 0xFFF, leaving half an opcode at the end of memory
+        let cpu = exec(&[0x1FFF], QuirkProfile::Vip, 1, |_| {});
+        assert_eq!(Debugger::step_over_target(&cpu), Err(EmuError::MemoryOutOfBounds { pc: 0xFFF, addr: 0x1000 }));
+    }
+
+    #[test]
+    fn test_format_memory() {
+        let cpu = exec(&[], QuirkProfile::Vip, 0, |_| {});
+        assert_eq!(Debugger::format_memory(&cpu, 0x0, 10), "0x0000: f0 90 90 90 f0 20 60 20\n0x0008: 20 70");
+        assert_eq!(Debugger::format_memory(&cpu, 0xFFE, 16), "0x0ffe: 00 00");
+    }
+}
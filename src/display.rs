@@ -1,65 +1,213 @@
-use sdl2::pixels::Color;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
-use sdl2::rect::Rect;
-
-pub struct Display {
-    canvas: Canvas<Window>,
-    vram: [[bool; 32]; 64], //access as vram[x][y]
-    changed: bool,
-    scale: u8,
-}
+pub const MAX_WIDTH: usize = 128;
+pub const MAX_HEIGHT: usize = 64;
+
+pub const PLANE_COUNT: u8 = 2;
 
-impl Display {
-    pub fn new(sdl_context: &sdl2::Sdl, scale: u8) -> Self {
-        let vram = [[false; 32]; 64];
-        let video_subsystem = sdl_context.video().unwrap();
+// Coordinates are in pixels of the current mode: 64x32 in low resolution and
+// 128x64 in the SUPER-CHIP high resolution mode. Pixels are colour indices
+// with one bit per XO-CHIP bitplane, so plain CHIP-8 only ever uses 0 and 1.
+pub trait Display {
+    fn clear(&mut self);
+    fn hires(&self) -> bool;
+    // Switching resolution also clears the screen.
+    fn set_hires(&mut self, hires: bool);
+    fn vram_get(&self, x: u8, y: u8) -> u8;
+    fn vram_set(&mut self, x: u8, y: u8, color: u8);
+    fn redraw(&mut self);
+
+    fn width(&self) -> u8 {
+        if self.hires() { 128 } else { 64 }
+    }
 
-        let window = video_subsystem.window("Chip-8 Terminal Window", 64 * u32::from(scale), 32 * u32::from(scale))
-        .position_centered()
-        .build()
-        .unwrap();
+    fn height(&self) -> u8 {
+        if self.hires() { 64 } else { 32 }
+    }
 
-        let canvas = window.into_canvas().build().unwrap();
+    // The plane arguments are bitmasks of the planes to touch, the rest keep their pixels
+    fn clear_planes(&mut self, planes: u8) {
+        for x in 0..self.width() {
+            for y in 0..self.height() {
+                let color = self.vram_get(x, y) & !planes;
+                self.vram_set(x, y, color);
+            }
+        }
+    }
+
+    fn scroll_down(&mut self, rows: u8, planes: u8) {
+        for y in (0..self.height()).rev() {
+            for x in 0..self.width() {
+                let moved = if y >= rows { self.vram_get(x, y - rows) } else { 0 };
+                let color = (self.vram_get(x, y) & !planes) | (moved & planes);
+                self.vram_set(x, y, color);
+            }
+        }
+    }
+
+    fn scroll_right(&mut self, cols: u8, planes: u8) {
+        for x in (0..self.width()).rev() {
+            for y in 0..self.height() {
+                let moved = if x >= cols { self.vram_get(x - cols, y) } else { 0 };
+                let color = (self.vram_get(x, y) & !planes) | (moved & planes);
+                self.vram_set(x, y, color);
+            }
+        }
+    }
 
-        Display {
-            canvas,
-            vram,
-            changed: true,
-            scale
+    fn scroll_left(&mut self, cols: u8, planes: u8) {
+        let width = self.width();
+        for x in 0..width {
+            for y in 0..self.height() {
+                let moved = if u16::from(x) + u16::from(cols) < u16::from(width) { self.vram_get(x + cols, y) } else { 0 };
+                let color = (self.vram_get(x, y) & !planes) | (moved & planes);
+                self.vram_set(x, y, color);
+            }
         }
     }
+}
+
+#[derive(Clone)]
+pub struct HeadlessDisplay {
+    vram: [[u8; MAX_HEIGHT]; MAX_WIDTH], //access as vram[x][y]
+    hires: bool,
+    redraws: usize,
+}
 
-    pub fn redraw(&mut self) {
-        if !self.changed {
-            return;
+impl HeadlessDisplay {
+    pub fn new() -> Self {
+        HeadlessDisplay {
+            vram: [[0; MAX_HEIGHT]; MAX_WIDTH],
+            hires: false,
+            redraws: 0,
         }
+    }
+
+    pub fn redraws(&self) -> usize {
+        self.redraws
+    }
 
-        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
-        self.canvas.clear();
-        self.canvas.set_draw_color(Color::RGB(0, 255, 0));
-        for y in 0u8..32 {
-            for x in 0u8..64 {
-                if self.vram_get(x, y) {
-                    self.canvas.fill_rect(Rect::new(i32::from(x) * i32::from(self.scale), i32::from(y) * i32::from(self.scale), u32::from(self.scale), u32::from(self.scale))).unwrap();
-                }
+    pub fn to_ascii(&self) -> String {
+        let mut res = String::with_capacity((usize::from(self.width()) + 1) * usize::from(self.height()));
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                res.push(['.', '#', '+', '%'][usize::from(self.vram_get(x, y))]);
             }
+            res.push('\n');
         }
 
-        self.canvas.present();
-        self.changed = false;
+        res
     }
+}
+
+impl Default for HeadlessDisplay {
+    fn default() -> Self {
+        HeadlessDisplay::new()
+    }
+}
 
-    pub fn clear(&mut self) {
-        self.vram = [[false; 32]; 64];
+impl Display for HeadlessDisplay {
+    fn clear(&mut self) {
+        self.vram = [[0; MAX_HEIGHT]; MAX_WIDTH];
     }
 
-    pub fn vram_get(&self, x: u8, y: u8) -> bool {
+    fn hires(&self) -> bool {
+        self.hires
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.clear();
+    }
+
+    fn vram_get(&self, x: u8, y: u8) -> u8 {
         self.vram[usize::from(x)][usize::from(y)]
     }
 
-    pub fn vram_set(&mut self, x: u8, y: u8, new: bool) {
-        self.vram[usize::from(x)][usize::from(y)] = new;
-        self.changed = true;
+    fn vram_set(&mut self, x: u8, y: u8, color: u8) {
+        assert!(color < 1 << PLANE_COUNT);
+        self.vram[usize::from(x)][usize::from(y)] = color;
+    }
+
+    fn redraw(&mut self) {
+        self.redraws += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headless_init_blank() {
+        let display = HeadlessDisplay::new();
+
+        for x in 0..64 {
+            for y in 0..32 {
+                assert_eq!(display.vram_get(x, y), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_headless_set_and_clear() {
+        let mut display = HeadlessDisplay::new();
+
+        display.vram_set(63, 31, 1);
+        assert_eq!(display.vram_get(63, 31), 1);
+        assert_eq!(display.to_ascii().lines().nth(31).unwrap().chars().nth(63), Some('#'));
+
+        display.clear();
+        assert_eq!(display.vram_get(63, 31), 0);
+    }
+
+    #[test]
+    fn test_hires() {
+        let mut display = HeadlessDisplay::new();
+
+        display.vram_set(0, 0, 1);
+        display.set_hires(true);
+        assert_eq!((display.width(), display.height()), (128, 64));
+        assert_eq!(display.vram_get(0, 0), 0);
+
+        display.vram_set(127, 63, 1);
+        assert_eq!(display.to_ascii().lines().count(), 64);
+        assert_eq!(display.to_ascii().lines().last().unwrap().len(), 128);
+    }
+
+    #[test]
+    fn test_scrolling() {
+        let mut display = HeadlessDisplay::new();
+
+        display.vram_set(10, 10, 1);
+        display.scroll_down(4, 1);
+        assert_eq!(display.vram_get(10, 14), 1);
+        assert_eq!(display.vram_get(10, 10), 0);
+
+        display.scroll_right(4, 1);
+        assert_eq!(display.vram_get(14, 14), 1);
+
+        display.scroll_left(4, 1);
+        display.scroll_left(4, 1);
+        assert_eq!(display.vram_get(6, 14), 1);
+        assert_eq!(display.vram_get(14, 14), 0);
+
+        display.vram_set(63, 0, 1);
+        display.scroll_right(4, 1);
+        assert_eq!(display.vram_get(63, 0), 0);
+    }
+
+    #[test]
+    fn test_planes() {
+        let mut display = HeadlessDisplay::new();
+
+        display.vram_set(0, 0, 3);
+        display.vram_set(1, 0, 1);
+        display.vram_set(2, 0, 2);
+        assert_eq!(&display.to_ascii()[..4], "%#+.");
+
+        display.scroll_right(1, 2);
+        assert_eq!((display.vram_get(0, 0), display.vram_get(1, 0), display.vram_get(2, 0)), (1, 3, 0));
+
+        display.clear_planes(1);
+        assert_eq!((display.vram_get(0, 0), display.vram_get(1, 0), display.vram_get(2, 0)), (0, 2, 0));
     }
 }
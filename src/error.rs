@@ -0,0 +1,38 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmuError {
+    InvalidOpcode { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+    MemoryOutOfBounds { pc: u16, addr: u32 },
+    InvalidKey { pc: u16, key: u8 },
+    MovieDesync { frame: u64, expected: u64, found: u64 },
+    RomTooLarge { size: usize, max: usize },
+    UserQuit,
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmuError::InvalidOpcode { pc, opcode } => write!(f, "invalid opcode 0x{:04x} at 0x{:04x}", opcode, pc),
+            EmuError::StackOverflow { pc } => write!(f, "stack overflow at 0x{:04x}", pc),
+            EmuError::StackUnderflow { pc } => write!(f, "stack underflow at 0x{:04x}", pc),
+            EmuError::MemoryOutOfBounds { pc, addr } => {
+                write!(f, "memory access out of bounds at 0x{:04x} (address 0x{:04x})", pc, addr)
+            }
+            EmuError::InvalidKey { pc, key } => write!(f, "invalid key 0x{:02x} queried at 0x{:04x}", key, pc),
+            EmuError::MovieDesync { frame, expected, found } => write!(
+                f,
+                "movie desynced at frame {} (state hash 0x{:016x}, recorded 0x{:016x})",
+                frame, found, expected
+            ),
+            EmuError::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes, but only {} bytes fit in memory", size, max)
+            }
+            EmuError::UserQuit => write!(f, "user quit"),
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
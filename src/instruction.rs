@@ -0,0 +1,371 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Cls,
+    Ret,
+    ScdNibble(u8),
+    Scr,
+    Scl,
+    Exit,
+    Low,
+    High,
+    JpAddr(u16),
+    CallAddr(u16),
+    SeVxByte(u8, u8),
+    SneVxByte(u8, u8),
+    SeVxVy(u8, u8),
+    LdVxByte(u8, u8),
+    AddVxByte(u8, u8),
+    LdVxVy(u8, u8),
+    OrVxVy(u8, u8),
+    AndVxVy(u8, u8),
+    XorVxVy(u8, u8),
+    AddVxVy(u8, u8),
+    SubVxVy(u8, u8),
+    ShrVxVy(u8, u8),
+    SubnVxVy(u8, u8),
+    ShlVxVy(u8, u8),
+    SneVxVy(u8, u8),
+    LdIAddr(u16),
+    JpV0Addr(u16),
+    RndVxByte(u8, u8),
+    DrwVxVyNibble(u8, u8, u8),
+    SkpVx(u8),
+    SknpVx(u8),
+    LdVxDt(u8),
+    LdVxK(u8),
+    LdDtVx(u8),
+    LdStVx(u8),
+    AddIVx(u8),
+    LdFVx(u8),
+    LdBVx(u8),
+    LdIVx(u8),
+    LdVxI(u8),
+    LdHfVx(u8),
+    LdRVx(u8),
+    LdVxR(u8),
+    SaveVxVy(u8, u8),
+    LoadVxVy(u8, u8),
+    LdILong,
+    PlaneN(u8),
+    LdAudioI,
+    LdPitchVx(u8),
+}
+
+// The machine that introduced an instruction. Each set includes the ones
+// before it, CHIP-48 added no instructions of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InstructionSet {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown opcode 0x{:04x}", self.opcode)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+    let nnn = opcode & 0x0FFF;
+    let nn = (opcode & 0x00FF) as u8;
+    let nibbles = (
+            ((opcode & 0xF000) >> 12) as u8,
+            ((opcode & 0x0F00) >> 8) as u8,
+            ((opcode & 0x00F0) >> 4) as u8,
+            (opcode & 0x000F) as u8
+        );
+
+    let res = match nibbles {
+        (0x0, 0x0, 0xE, 0x0) => Instruction::Cls,
+        (0x0, 0x0, 0xE, 0xE) => Instruction::Ret,
+        (0x0, 0x0, 0xC, n) => Instruction::ScdNibble(n),
+        (0x0, 0x0, 0xF, 0xB) => Instruction::Scr,
+        (0x0, 0x0, 0xF, 0xC) => Instruction::Scl,
+        (0x0, 0x0, 0xF, 0xD) => Instruction::Exit,
+        (0x0, 0x0, 0xF, 0xE) => Instruction::Low,
+        (0x0, 0x0, 0xF, 0xF) => Instruction::High,
+        (0x1, _, _, _) => Instruction::JpAddr(nnn),
+        (0x2, _, _, _) => Instruction::CallAddr(nnn),
+        (0x3, x, _, _) => Instruction::SeVxByte(x, nn),
+        (0x4, x, _, _) => Instruction::SneVxByte(x, nn),
+        (0x5, x, y, 0x0) => Instruction::SeVxVy(x, y),
+        (0x5, x, y, 0x2) => Instruction::SaveVxVy(x, y),
+        (0x5, x, y, 0x3) => Instruction::LoadVxVy(x, y),
+        (0x6, x, _, _) => Instruction::LdVxByte(x, nn),
+        (0x7, x, _, _) => Instruction::AddVxByte(x, nn),
+        (0x8, x, y, 0x0) => Instruction::LdVxVy(x, y),
+        (0x8, x, y, 0x1) => Instruction::OrVxVy(x, y),
+        (0x8, x, y, 0x2) => Instruction::AndVxVy(x, y),
+        (0x8, x, y, 0x3) => Instruction::XorVxVy(x, y),
+        (0x8, x, y, 0x4) => Instruction::AddVxVy(x, y),
+        (0x8, x, y, 0x5) => Instruction::SubVxVy(x, y),
+        (0x8, x, y, 0x6) => Instruction::ShrVxVy(x, y),
+        (0x8, x, y, 0x7) => Instruction::SubnVxVy(x, y),
+        (0x8, x, y, 0xE) => Instruction::ShlVxVy(x, y),
+        (0x9, x, y, 0x0) => Instruction::SneVxVy(x, y),
+        (0xA, _, _, _) => Instruction::LdIAddr(nnn),
+        (0xB, _, _, _) => Instruction::JpV0Addr(nnn),
+        (0xC, x, _, _) => Instruction::RndVxByte(x, nn),
+        (0xD, x, y, n) => Instruction::DrwVxVyNibble(x, y, n),
+        (0xE, x, 0x9, 0xE) => Instruction::SkpVx(x),
+        (0xE, x, 0xA, 0x1) => Instruction::SknpVx(x),
+        (0xF, x, 0x0, 0x7) => Instruction::LdVxDt(x),
+        (0xF, x, 0x0, 0xA) => Instruction::LdVxK(x),
+        (0xF, x, 0x1, 0x5) => Instruction::LdDtVx(x),
+        (0xF, x, 0x1, 0x8) => Instruction::LdStVx(x),
+        (0xF, x, 0x1, 0xE) => Instruction::AddIVx(x),
+        (0xF, x, 0x2, 0x9) => Instruction::LdFVx(x),
+        (0xF, x, 0x3, 0x3) => Instruction::LdBVx(x),
+        (0xF, x, 0x5, 0x5) => Instruction::LdIVx(x),
+        (0xF, x, 0x6, 0x5) => Instruction::LdVxI(x),
+        (0xF, x, 0x3, 0x0) => Instruction::LdHfVx(x),
+        (0xF, x, 0x7, 0x5) => Instruction::LdRVx(x),
+        (0xF, x, 0x8, 0x5) => Instruction::LdVxR(x),
+        (0xF, 0x0, 0x0, 0x0) => Instruction::LdILong,
+        (0xF, n, 0x0, 0x1) if n <= 0x3 => Instruction::PlaneN(n),
+        (0xF, 0x0, 0x0, 0x2) => Instruction::LdAudioI,
+        (0xF, x, 0x3, 0xA) => Instruction::LdPitchVx(x),
+        (_, _, _, _) => return Err(DecodeError { opcode }),
+    };
+
+    Ok(res)
+}
+
+impl Instruction {
+    pub fn set(&self) -> InstructionSet {
+        match *self {
+            Instruction::ScdNibble(_)
+            | Instruction::Scr
+            | Instruction::Scl
+            | Instruction::Exit
+            | Instruction::Low
+            | Instruction::High
+            | Instruction::LdHfVx(_)
+            | Instruction::LdRVx(_)
+            | Instruction::LdVxR(_) => InstructionSet::SuperChip,
+            Instruction::SaveVxVy(..)
+            | Instruction::LoadVxVy(..)
+            | Instruction::LdILong
+            | Instruction::PlaneN(_)
+            | Instruction::LdAudioI
+            | Instruction::LdPitchVx(_) => InstructionSet::XoChip,
+            _ => InstructionSet::Chip8,
+        }
+    }
+
+    pub fn encode(&self) -> u16 {
+        let xy = |op: u16, x: u8, y: u8, n: u16| op | (u16::from(x) << 8) | (u16::from(y) << 4) | n;
+        let xnn = |op: u16, x: u8, nn: u8| op | (u16::from(x) << 8) | u16::from(nn);
+
+        match *self {
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScdNibble(n) => 0x00C0 | u16::from(n),
+            Instruction::Scr => 0x00FB,
+            Instruction::Scl => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Low => 0x00FE,
+            Instruction::High => 0x00FF,
+            Instruction::JpAddr(addr) => 0x1000 | addr,
+            Instruction::CallAddr(addr) => 0x2000 | addr,
+            Instruction::SeVxByte(x, byte) => xnn(0x3000, x, byte),
+            Instruction::SneVxByte(x, byte) => xnn(0x4000, x, byte),
+            Instruction::SeVxVy(x, y) => xy(0x5000, x, y, 0x0),
+            Instruction::LdVxByte(x, byte) => xnn(0x6000, x, byte),
+            Instruction::AddVxByte(x, byte) => xnn(0x7000, x, byte),
+            Instruction::LdVxVy(x, y) => xy(0x8000, x, y, 0x0),
+            Instruction::OrVxVy(x, y) => xy(0x8000, x, y, 0x1),
+            Instruction::AndVxVy(x, y) => xy(0x8000, x, y, 0x2),
+            Instruction::XorVxVy(x, y) => xy(0x8000, x, y, 0x3),
+            Instruction::AddVxVy(x, y) => xy(0x8000, x, y, 0x4),
+            Instruction::SubVxVy(x, y) => xy(0x8000, x, y, 0x5),
+            Instruction::ShrVxVy(x, y) => xy(0x8000, x, y, 0x6),
+            Instruction::SubnVxVy(x, y) => xy(0x8000, x, y, 0x7),
+            Instruction::ShlVxVy(x, y) => xy(0x8000, x, y, 0xE),
+            Instruction::SneVxVy(x, y) => xy(0x9000, x, y, 0x0),
+            Instruction::LdIAddr(addr) => 0xA000 | addr,
+            Instruction::JpV0Addr(addr) => 0xB000 | addr,
+            Instruction::RndVxByte(x, byte) => xnn(0xC000, x, byte),
+            Instruction::DrwVxVyNibble(x, y, n) => xy(0xD000, x, y, u16::from(n)),
+            Instruction::SkpVx(x) => xnn(0xE000, x, 0x9E),
+            Instruction::SknpVx(x) => xnn(0xE000, x, 0xA1),
+            Instruction::LdVxDt(x) => xnn(0xF000, x, 0x07),
+            Instruction::LdVxK(x) => xnn(0xF000, x, 0x0A),
+            Instruction::LdDtVx(x) => xnn(0xF000, x, 0x15),
+            Instruction::LdStVx(x) => xnn(0xF000, x, 0x18),
+            Instruction::AddIVx(x) => xnn(0xF000, x, 0x1E),
+            Instruction::LdFVx(x) => xnn(0xF000, x, 0x29),
+            Instruction::LdBVx(x) => xnn(0xF000, x, 0x33),
+            Instruction::LdIVx(x) => xnn(0xF000, x, 0x55),
+            Instruction::LdVxI(x) => xnn(0xF000, x, 0x65),
+            Instruction::LdHfVx(x) => xnn(0xF000, x, 0x30),
+            Instruction::LdRVx(x) => xnn(0xF000, x, 0x75),
+            Instruction::LdVxR(x) => xnn(0xF000, x, 0x85),
+            Instruction::SaveVxVy(x, y) => xy(0x5000, x, y, 0x2),
+            Instruction::LoadVxVy(x, y) => xy(0x5000, x, y, 0x3),
+            Instruction::LdILong => 0xF000,
+            Instruction::PlaneN(n) => xnn(0xF000, n, 0x01),
+            Instruction::LdAudioI => 0xF002,
+            Instruction::LdPitchVx(x) => xnn(0xF000, x, 0x3A),
+        }
+    }
+}
+
+// One line per instruction, in a form the assembler accepts back. Words that
+// don't decode are emitted as data.
+pub fn disassemble(bytes: &[u8], origin: u16) -> Vec<String> {
+    let mut res = Vec::new();
+    let word_at = |pos: usize| (u16::from(bytes[pos]) << 8) | u16::from(bytes[pos + 1]);
+
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let addr = usize::from(origin) + pos;
+        if pos + 1 == bytes.len() {
+            res.push(format!("[0x{:04x}]: DB 0x{:02x}", addr, bytes[pos]));
+            break;
+        }
+
+        let opcode = word_at(pos);
+        match decode(opcode) {
+            Ok(Instruction::LdILong) if pos + 3 < bytes.len() => {
+                res.push(format!("[0x{:04x}]: LD I, LONG 0x{:04x}", addr, word_at(pos + 2)));
+                pos += 2;
+            }
+            Ok(instruction) if instruction != Instruction::LdILong => res.push(format!("[0x{:04x}]: {}", addr, instruction)),
+            _ => res.push(format!("[0x{:04x}]: DW 0x{:04x}", addr, opcode)),
+        }
+        pos += 2;
+    }
+
+    res
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScdNibble(n) => write!(f, "SCD 0x{:01x}", n),
+            Instruction::Scr => write!(f, "SCR"),
+            Instruction::Scl => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::JpAddr(addr) => write!(f, "JP 0x{:04x}", addr),
+            Instruction::CallAddr(addr) => write!(f, "CALL 0x{:04x}", addr),
+            Instruction::SeVxByte(x, byte) => write!(f, "SE V{:01x}, 0x{:02x}", x, byte),
+            Instruction::SneVxByte(x, byte) => write!(f, "SNE V{:01x}, 0x{:02x}", x, byte),
+            Instruction::SeVxVy(x, y) => write!(f, "SE V{:01x}, V{:01x}", x, y),
+            Instruction::LdVxByte(x, byte) => write!(f, "LD V{:01x}, 0x{:02x}", x, byte),
+            Instruction::AddVxByte(x, byte) => write!(f, "ADD V{:01x}, 0x{:02x}", x, byte),
+            Instruction::LdVxVy(x, y) => write!(f, "LD V{:01x}, V{:01x}", x, y),
+            Instruction::OrVxVy(x, y) => write!(f, "OR V{:01x}, V{:01x}", x, y),
+            Instruction::AndVxVy(x, y) => write!(f, "AND V{:01x}, V{:01x}", x, y),
+            Instruction::XorVxVy(x, y) => write!(f, "XOR V{:01x}, V{:01x}", x, y),
+            Instruction::AddVxVy(x, y) => write!(f, "ADD V{:01x}, V{:01x}", x, y),
+            Instruction::SubVxVy(x, y) => write!(f, "SUB V{:01x}, V{:01x}", x, y),
+            Instruction::ShrVxVy(x, y) => write!(f, "SHR V{:01x}, V{:01x}", x, y),
+            Instruction::SubnVxVy(x, y) => write!(f, "SUBN V{:01x}, V{:01x}", x, y),
+            Instruction::ShlVxVy(x, y) => write!(f, "SHL V{:01x}, V{:01x}", x, y),
+            Instruction::SneVxVy(x, y) => write!(f, "SNE V{:01x}, V{:01x}", x, y),
+            Instruction::LdIAddr(addr) => write!(f, "LD I, 0x{:04x}", addr),
+            Instruction::JpV0Addr(addr) => write!(f, "JP V0, 0x{:04x}", addr),
+            Instruction::RndVxByte(x, byte) => write!(f, "RND V{:01x}, 0x{:02x}", x, byte),
+            Instruction::DrwVxVyNibble(x, y, n) => write!(f, "DRW V{:01x}, V{:01x}, 0x{:01x}", x, y, n),
+            Instruction::SkpVx(x) => write!(f, "SKP V{:01x}", x),
+            Instruction::SknpVx(x) => write!(f, "SKNP V{:01x}", x),
+            Instruction::LdVxDt(x) => write!(f, "LD V{:01x}, DT", x),
+            Instruction::LdVxK(x) => write!(f, "LD V{:01x}, K", x),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{:01x}", x),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{:01x}", x),
+            Instruction::AddIVx(x) => write!(f, "ADD I, V{:01x}", x),
+            Instruction::LdFVx(x) => write!(f, "LD F, V{:01x}", x),
+            Instruction::LdBVx(x) => write!(f, "LD B, V{:01x}", x),
+            Instruction::LdIVx(x) => write!(f, "LD [I], V{:01x}", x),
+            Instruction::LdVxI(x) => write!(f, "LD V{:01x}, [I]", x),
+            Instruction::LdHfVx(x) => write!(f, "LD HF, V{:01x}", x),
+            Instruction::LdRVx(x) => write!(f, "LD R, V{:01x}", x),
+            Instruction::LdVxR(x) => write!(f, "LD V{:01x}, R", x),
+            Instruction::SaveVxVy(x, y) => write!(f, "SAVE V{:01x}, V{:01x}", x, y),
+            Instruction::LoadVxVy(x, y) => write!(f, "LOAD V{:01x}, V{:01x}", x, y),
+            Instruction::LdILong => write!(f, "LD I, LONG"),
+            Instruction::PlaneN(n) => write!(f, "PLANE 0x{:01x}", n),
+            Instruction::LdAudioI => write!(f, "LD AUDIO, [I]"),
+            Instruction::LdPitchVx(x) => write!(f, "LD PITCH, V{:01x}", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(0x00E0), Ok(Instruction::Cls));
+        assert_eq!(decode(0x00EE), Ok(Instruction::Ret));
+        assert_eq!(decode(0x1234), Ok(Instruction::JpAddr(0x234)));
+        assert_eq!(decode(0x3A42), Ok(Instruction::SeVxByte(0xA, 0x42)));
+        assert_eq!(decode(0x8126), Ok(Instruction::ShrVxVy(0x1, 0x2)));
+        assert_eq!(decode(0xBFFF), Ok(Instruction::JpV0Addr(0xFFF)));
+        assert_eq!(decode(0xD12F), Ok(Instruction::DrwVxVyNibble(0x1, 0x2, 0xF)));
+        assert_eq!(decode(0xF565), Ok(Instruction::LdVxI(0x5)));
+        assert_eq!(decode(0x00C4), Ok(Instruction::ScdNibble(0x4)));
+        assert_eq!(decode(0x00FF), Ok(Instruction::High));
+        assert_eq!(decode(0xF330), Ok(Instruction::LdHfVx(0x3)));
+        assert_eq!(decode(0xF785), Ok(Instruction::LdVxR(0x7)));
+        assert_eq!(decode(0x5122), Ok(Instruction::SaveVxVy(0x1, 0x2)));
+        assert_eq!(decode(0x5E03), Ok(Instruction::LoadVxVy(0xE, 0x0)));
+        assert_eq!(decode(0xF000), Ok(Instruction::LdILong));
+        assert_eq!(decode(0xF301), Ok(Instruction::PlaneN(0x3)));
+        assert_eq!(decode(0xF002), Ok(Instruction::LdAudioI));
+        assert_eq!(decode(0xF43A), Ok(Instruction::LdPitchVx(0x4)));
+    }
+
+    #[test]
+    fn test_decode_unknown() {
+        for &opcode in &[0x0000, 0x00E1, 0x00FA, 0x5121, 0x8128, 0x9121, 0xE19F, 0xF1FF, 0xF401, 0xF102] {
+            assert_eq!(decode(opcode), Err(DecodeError { opcode }));
+        }
+        assert_eq!(DecodeError { opcode: 0x5121 }.to_string(), "unknown opcode 0x5121");
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(decode(0x6A0F).unwrap().to_string(), "LD Va, 0x0f");
+        assert_eq!(decode(0x7110).unwrap().to_string(), "ADD V1, 0x10");
+        assert_eq!(decode(0xB200).unwrap().to_string(), "JP V0, 0x0200");
+        assert_eq!(decode(0xD015).unwrap().to_string(), "DRW V0, V1, 0x5");
+        assert_eq!(decode(0xF355).unwrap().to_string(), "LD [I], V3");
+    }
+
+    #[test]
+    fn test_encode_inverts_decode() {
+        for opcode in 0..=0xFFFF {
+            if let Ok(instruction) = decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let lines = disassemble(&[0x00, 0xE0, 0x12, 0x00, 0x00, 0x00, 0xAB], 0x200);
+
+        assert_eq!(lines, vec!["[0x0200]: CLS", "[0x0202]: JP 0x0200", "[0x0204]: DW 0x0000", "[0x0206]: DB 0xab"]);
+
+        let lines = disassemble(&[0xF0, 0x00, 0x12, 0x34, 0xF0, 0x00], 0x200);
+
+        assert_eq!(lines, vec!["[0x0200]: LD I, LONG 0x1234", "[0x0204]: DW 0xf000"]);
+    }
+}
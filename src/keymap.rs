@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+// Host keys for CHIP-8 keys 0 to F on the usual 1234/QWER/ASDF/ZXCV layout,
+// plus the d-pad on 2/4/6/8 and the A button on 5, which suits most games.
+// Keys are SDL scancode names, which follow key positions rather than labels.
+// Controller buttons are "Pad <BUTTON>", or "Pad<N> <BUTTON>" for player N.
+const DEFAULT_KEYS: [&[&str]; 16] = [
+    &["X"],
+    &["1"],
+    &["2", "Pad DPUp"],
+    &["3"],
+    &["Q", "Pad DPLeft"],
+    &["W", "Pad A"],
+    &["E", "Pad DPRight"],
+    &["A"],
+    &["S", "Pad DPDown"],
+    &["D"],
+    &["Z"],
+    &["C"],
+    &["4"],
+    &["R"],
+    &["F"],
+    &["V"],
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeymapError {
+    Syntax { line: usize, reason: &'static str },
+    // the same CHIP-8 key is bound twice in one section
+    Rebound { line: usize, key: u8 },
+    Gap { section: String, key: u8 },
+    Duplicate { section: String, host_key: String },
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeymapError::Syntax { line, reason } => write!(f, "line {}: {}", line, reason),
+            KeymapError::Rebound { line, key } => write!(f, "line {}: key {:X} is already bound in this section", line, key),
+            KeymapError::Gap { section, key } => write!(f, "[{}]: key {:X} has no host key", section, key),
+            KeymapError::Duplicate { section, host_key } => {
+                write!(f, "[{}]: host key '{}' is bound to more than one key", section, host_key)
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeymapError {}
+
+// The host keys bound to each CHIP-8 key. Any of them presses the key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [Vec<String>; 16],
+}
+
+impl Keymap {
+    // Every key needs at least one host key, and no host key may press two keys
+    pub fn new(keys: [Vec<String>; 16], section: &str) -> Result<Self, KeymapError> {
+        if let Some(key) = keys.iter().position(Vec::is_empty) {
+            return Err(KeymapError::Gap { section: section.to_string(), key: key as u8 });
+        }
+
+        let mut seen: Vec<&str> = Vec::new();
+        for host_key in keys.iter().flatten() {
+            if seen.iter().any(|other| other.eq_ignore_ascii_case(host_key)) {
+                return Err(KeymapError::Duplicate { section: section.to_string(), host_key: host_key.clone() });
+            }
+            seen.push(host_key);
+        }
+
+        Ok(Keymap { keys })
+    }
+
+    pub fn host_keys(&self, key: u8) -> &[String] {
+        assert!(key < 16);
+
+        &self.keys[usize::from(key)]
+    }
+
+    // The CHIP-8 key a host key is bound to, ignoring case
+    pub fn key_for(&self, host_key: &str) -> Option<u8> {
+        self.keys
+            .iter()
+            .position(|host_keys| host_keys.iter().any(|bound| bound.eq_ignore_ascii_case(host_key)))
+            .map(|key| key as u8)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keys: [Vec<String>; 16] = Default::default();
+        for (host_keys, names) in keys.iter_mut().zip(DEFAULT_KEYS.iter()) {
+            host_keys.extend(names.iter().map(|name| name.to_string()));
+        }
+
+        Keymap { keys }
+    }
+}
+
+// A keymap config file in INI format. [keys] replaces the default layout and
+// must bind all sixteen keys, [rom.<FILE>] sections only rebind the keys they
+// list for the ROM with that file name. Host keys are separated by commas, so
+// the comma key itself is written as Comma:
+//
+//     [keys]
+//     1 = 1
+//     4 = Q, Up
+//     ...
+//     [rom.pong.ch8]
+//     1 = W
+//     4 = S
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeymapConfig {
+    default: Keymap,
+    roms: HashMap<String, Keymap>,
+}
+
+type Section = (String, [Option<Vec<String>>; 16]);
+
+impl KeymapConfig {
+    pub fn parse(text: &str) -> Result<Self, KeymapError> {
+        let mut sections: Vec<Section> = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let syntax = |reason| KeymapError::Syntax { line: i + 1, reason };
+
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                let name = line.strip_prefix('[').and_then(|name| name.strip_suffix(']')).ok_or(syntax("unclosed section header"))?.trim();
+                let known = name == "keys" || name.strip_prefix("rom.").is_some_and(|rom| !rom.is_empty());
+                if !known {
+                    return Err(syntax("sections are [keys] or [rom.<FILE>]"));
+                }
+                if sections.iter().any(|(other, _)| other == name) {
+                    return Err(syntax("section appears twice"));
+                }
+                sections.push((name.to_string(), Default::default()));
+                continue;
+            }
+
+            let (key, host_keys) = line.split_once('=').ok_or(syntax("expected <KEY> = <HOST KEY>, ..."))?;
+            let (_, bindings) = sections.last_mut().ok_or(syntax("binding outside of a section"))?;
+
+            let key = Some(key.trim()).filter(|key| key.len() == 1).and_then(|key| u8::from_str_radix(key, 16).ok()).ok_or(syntax("keys are 0 to F"))?;
+            let binding = &mut bindings[usize::from(key)];
+            if binding.is_some() {
+                return Err(KeymapError::Rebound { line: i + 1, key });
+            }
+            // an empty list unbinds the key, which Keymap::new rejects
+            *binding = Some(host_keys.split(',').map(str::trim).filter(|name| !name.is_empty()).map(String::from).collect());
+        }
+
+        let mut default = Keymap::default();
+        if let Some((_, bindings)) = sections.iter().find(|(name, _)| name == "keys") {
+            let keys = bindings.clone().map(Option::unwrap_or_default);
+            default = Keymap::new(keys, "keys")?;
+        }
+
+        let mut roms = HashMap::new();
+        for (name, bindings) in &sections {
+            if let Some(rom) = name.strip_prefix("rom.") {
+                let mut keys = default.keys.clone();
+                for (host_keys, binding) in keys.iter_mut().zip(bindings.iter()) {
+                    if let Some(binding) = binding {
+                        *host_keys = binding.clone();
+                    }
+                }
+                roms.insert(rom.to_string(), Keymap::new(keys, name)?);
+            }
+        }
+
+        Ok(KeymapConfig { default, roms })
+    }
+
+    // The keymap for a ROM, looked up by its file name
+    pub fn keymap(&self, rom_path: &str) -> &Keymap {
+        Path::new(rom_path)
+            .file_name()
+            .and_then(|name| self.roms.get(name.to_string_lossy().as_ref()))
+            .unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: &str = "\
+# the keypad on the number pad
+[keys]
+1 = Keypad 7
+2 = Keypad 8
+3 = Keypad 9
+C = Keypad /
+4 = Keypad 4
+5 = Keypad 5
+6 = Keypad 6
+D = Keypad *
+7 = Keypad 1
+8 = Keypad 2
+9 = Keypad 3
+E = Keypad -
+A = Keypad 0, 0
+0 = Keypad .
+B = Keypad Enter
+F = Keypad +
+";
+
+    #[test]
+    fn test_default_layout() {
+        let keymap = Keymap::default();
+
+        assert_eq!(keymap.host_keys(0x1), ["1"]);
+        assert_eq!(keymap.host_keys(0xC), ["4"]);
+        assert_eq!(keymap.host_keys(0x0), ["X"]);
+        assert_eq!(keymap.host_keys(0x8), ["S", "Pad DPDown"]);
+        assert_eq!(keymap.key_for("v"), Some(0xF));
+        assert_eq!(keymap.key_for("5"), None);
+        assert_eq!(KeymapConfig::parse(""), Ok(KeymapConfig::default()));
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = LAYOUT.to_string() + "\n[rom.pong.ch8]\n1 = Up, W, Pad1 DPUp\n4 = Down, S, Pad1 DPDown\nC = Pad2 DPUp\nD = Pad2 DPDown\n";
+        let config = KeymapConfig::parse(&text).unwrap();
+
+        let keymap = config.keymap("games/tetris.ch8");
+        assert_eq!(keymap.key_for("Keypad 4"), Some(0x4));
+        assert_eq!(keymap.key_for("keypad enter"), Some(0xB));
+        assert_eq!(keymap.key_for("0"), Some(0xA));
+        assert_eq!(keymap.key_for("W"), None);
+
+        let pong = config.keymap("roms/pong.ch8");
+        assert_eq!(pong.host_keys(0x1), ["Up", "W", "Pad1 DPUp"]);
+        assert_eq!(pong.key_for("pad2 dpdown"), Some(0xD));
+        assert_eq!(pong.key_for("down"), Some(0x4));
+        assert_eq!(pong.key_for("Keypad 4"), None);
+        assert_eq!(pong.key_for("Keypad ."), Some(0x0));
+    }
+
+    #[test]
+    fn test_rom_sections_override_the_builtin_layout() {
+        let config = KeymapConfig::parse("[rom.brix.ch8]\n4 = Left\n6 = Right\n").unwrap();
+
+        let keymap = config.keymap("brix.ch8");
+        assert_eq!(keymap.host_keys(0x4), ["Left"]);
+        assert_eq!(keymap.key_for("Q"), None);
+        assert_eq!(keymap.key_for("W"), Some(0x5));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            KeymapConfig::parse(&LAYOUT.replace("A = Keypad 0, 0", "A =")),
+            Err(KeymapError::Gap { section: "keys".to_string(), key: 0xA })
+        );
+        assert_eq!(
+            KeymapConfig::parse("[keys]\n0 = Q"),
+            Err(KeymapError::Gap { section: "keys".to_string(), key: 0x1 })
+        );
+        assert_eq!(
+            KeymapConfig::parse("[rom.a.ch8]\n1 = X"),
+            Err(KeymapError::Duplicate { section: "rom.a.ch8".to_string(), host_key: "X".to_string() })
+        );
+        assert_eq!(
+            KeymapConfig::parse("[rom.a.ch8]\n1 = Up, up"),
+            Err(KeymapError::Duplicate { section: "rom.a.ch8".to_string(), host_key: "up".to_string() })
+        );
+        assert_eq!(KeymapConfig::parse("[rom.a.ch8]\n1 = Up\n1 = Down"), Err(KeymapError::Rebound { line: 3, key: 0x1 }));
+
+        let syntax = |text| match KeymapConfig::parse(text) {
+            Err(KeymapError::Syntax { line, reason }) => (line, reason),
+            res => panic!("expected a syntax error, got {:?}", res),
+        };
+        assert_eq!(syntax("1 = Up"), (1, "binding outside of a section"));
+        assert_eq!(syntax("[keys\n"), (1, "unclosed section header"));
+        assert_eq!(syntax("[gamepad]"), (1, "sections are [keys] or [rom.<FILE>]"));
+        assert_eq!(syntax("[rom.a]\n[rom.a]"), (2, "section appears twice"));
+        assert_eq!(syntax("[rom.a]\n10 = Up"), (2, "keys are 0 to F"));
+        assert_eq!(syntax("[rom.a]\nUp"), (2, "expected <KEY> = <HOST KEY>, ..."));
+    }
+}
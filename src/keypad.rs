@@ -1,88 +1,137 @@
-use sdl2::event::Event;
-use sdl2::keyboard::Scancode;
+use crate::error::EmuError;
 
-pub struct Keypad {
-    events: sdl2::EventPump,
-}
+// Which of the sixteen keys are held down, one bit per key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeypadState(u16);
 
-impl Keypad {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
+impl KeypadState {
+    pub fn from_bits(bits: u16) -> Self {
+        KeypadState(bits)
+    }
 
-        Keypad {
-            events: sdl_context.event_pump().unwrap()
-        }
+    pub fn bits(self) -> u16 {
+        self.0
     }
 
-    pub fn check_for_exit(&mut self) {
-        for event in self.events.poll_iter() {
-            if let Event::Quit{ .. } = event {
-                panic!("Exiting!");
-            }
-        }
+    pub fn is_pressed(self, key: u8) -> bool {
+        assert!(key < 16);
+
+        self.0 & 1 << key != 0
     }
 
-    pub fn button_is_pressed(&mut self, key: u8) -> bool {
-        self.events.pump_events();
-        match Keypad::keypad_to_scancode(key) {
-            Some(code) => self.events.keyboard_state().is_scancode_pressed(code),
-            None => {
-                panic!("Unrecognized keypad button queried: {}", key)
-            }
+    pub fn set(&mut self, key: u8, pressed: bool) {
+        assert!(key < 16);
+
+        if pressed {
+            self.0 |= 1 << key;
+        } else {
+            self.0 &= !(1 << key);
         }
     }
 
-    pub fn next_button_pressed(&mut self) -> u8 {
-        loop {
-            self.check_for_exit();
-            for code in self.events.keyboard_state().pressed_scancodes() {
-                if let Some(keypad) = Keypad::scancode_to_keypad(code) {
-                    return keypad;
-                }
-            }
-        }
+    pub fn pressed_keys(self) -> impl Iterator<Item = u8> {
+        (0..16).filter(move |&key| self.is_pressed(key))
     }
+}
 
-    fn keypad_to_scancode(key: u8) -> Option<sdl2::keyboard::Scancode> {
-        match key {
-            0x1 => Some(Scancode::Num1),
-            0x2 => Some(Scancode::Num2),
-            0x3 => Some(Scancode::Num3),
-            0xC => Some(Scancode::Num4),
-            0x4 => Some(Scancode::Q),
-            0x5 => Some(Scancode::W),
-            0x6 => Some(Scancode::E),
-            0xD => Some(Scancode::R),
-            0x7 => Some(Scancode::A),
-            0x8 => Some(Scancode::S),
-            0x9 => Some(Scancode::D),
-            0xE => Some(Scancode::F),
-            0xA => Some(Scancode::Z),
-            0x0 => Some(Scancode::X),
-            0xB => Some(Scancode::C),
-            0xF => Some(Scancode::V),
-            _ => None,
-        }
+pub trait Keypad {
+    // Handles input that arrived since the last call and returns the keys
+    // held down now. The Cpu calls this once per emulated frame, and opcodes
+    // only see the state it returned.
+    fn poll(&mut self) -> KeypadState;
+    // Returns true once the user has asked to quit. Quit requests are noticed
+    // while polling.
+    fn check_for_exit(&mut self) -> bool;
+
+    // Called by the Cpu after every emulated frame, before polling. state_hash
+    // hashes memory and the screen, and is only worth calling when the result
+    // is needed.
+    fn end_frame(&mut self, _frame: u64, _state_hash: &dyn Fn() -> u64) -> Result<(), EmuError> {
+        Ok(())
     }
+}
+
+#[derive(Clone)]
+pub struct ScriptedKeypad {
+    pressed: KeypadState,
+    quit: bool,
+}
 
-    fn scancode_to_keypad(code: Scancode) -> Option<u8> {
-        match code {
-            Scancode::Num1 => Some(0x1),
-            Scancode::Num2 => Some(0x2),
-            Scancode::Num3 => Some(0x3),
-            Scancode::Num4 => Some(0xC),
-            Scancode::Q => Some(0x4),
-            Scancode::W => Some(0x5),
-            Scancode::E => Some(0x6),
-            Scancode::R => Some(0xD),
-            Scancode::A => Some(0x7),
-            Scancode::S => Some(0x8),
-            Scancode::D => Some(0x9),
-            Scancode::F => Some(0xE),
-            Scancode::Z => Some(0xA),
-            Scancode::X => Some(0x0),
-            Scancode::C => Some(0xB),
-            Scancode::V => Some(0xF),
-            _ => None,
+impl ScriptedKeypad {
+    pub fn new() -> Self {
+        ScriptedKeypad {
+            pressed: KeypadState::default(),
+            quit: false,
         }
     }
+
+    pub fn press(&mut self, key: u8) {
+        self.pressed.set(key, true);
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.pressed.set(key, false);
+    }
+
+    pub fn request_quit(&mut self) {
+        self.quit = true;
+    }
+}
+
+impl Default for ScriptedKeypad {
+    fn default() -> Self {
+        ScriptedKeypad::new()
+    }
+}
+
+impl Keypad for ScriptedKeypad {
+    fn poll(&mut self) -> KeypadState {
+        self.pressed
+    }
+
+    fn check_for_exit(&mut self) -> bool {
+        self.quit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keypad_state() {
+        let mut state = KeypadState::default();
+
+        state.set(0x0, true);
+        state.set(0xF, true);
+        state.set(0x7, true);
+        state.set(0x7, false);
+        assert_eq!(state.bits(), 0x8001);
+        assert!(state.is_pressed(0xF));
+        assert!(!state.is_pressed(0x7));
+        assert_eq!(state.pressed_keys().collect::<Vec<_>>(), vec![0x0, 0xF]);
+        assert_eq!(KeypadState::from_bits(0x8001), state);
+    }
+
+    #[test]
+    fn test_scripted_press_release() {
+        let mut keypad = ScriptedKeypad::new();
+
+        keypad.press(0xA);
+        assert!(keypad.poll().is_pressed(0xA));
+        assert!(!keypad.poll().is_pressed(0xB));
+
+        keypad.release(0xA);
+        assert!(!keypad.poll().is_pressed(0xA));
+    }
+
+    #[test]
+    fn test_scripted_quit() {
+        let mut keypad = ScriptedKeypad::new();
+
+        assert!(!keypad.check_for_exit());
+
+        keypad.request_quit();
+        assert!(keypad.check_for_exit());
+    }
 }
@@ -0,0 +1,18 @@
+pub mod assembler;
+pub mod audio;
+pub mod chip8;
+pub mod cpu;
+pub mod debugger;
+pub mod display;
+pub mod error;
+pub mod instruction;
+pub mod keymap;
+pub mod keypad;
+pub mod movie;
+pub mod quirks;
+pub mod ram;
+pub mod rewind;
+pub mod rng;
+pub mod screenshot;
+pub mod state;
+pub mod trace;
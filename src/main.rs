@@ -1,31 +1,312 @@
-mod cpu;
-mod display;
-mod keypad;
-mod ram;
+mod cli;
+mod repl;
+mod sdl;
 
-use crate::cpu::Cpu;
-use crate::display::Display;
-use crate::keypad::Keypad;
-use crate::ram::Ram;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-fn main() {
-    let sdl_context = sdl2::init().unwrap();
+use emulator::audio::{Audio, NullAudio};
+use emulator::chip8::Chip8;
+use emulator::cpu::{Cpu, StepOutcome};
+use emulator::debugger::Debugger;
+use emulator::error::EmuError;
+use emulator::display::{Display, HeadlessDisplay};
+use emulator::keymap::KeymapConfig;
+use emulator::keypad::ScriptedKeypad;
+use emulator::movie::{rom_hash, Movie, MovieHeader, MovieKeypad};
+use emulator::ram::Ram;
+use emulator::rng::SeededRng;
+use emulator::trace::Tracer;
+
+use crate::cli::{CliError, Mode, MovieOption, Options};
+use crate::repl::Resume;
+use crate::sdl::{Hotkey, SdlAudio, SdlDisplay, SdlKeypad};
+
+fn state_path(rom: &str, slot: u8) -> String {
+    format!("{}.state{}", rom, slot)
+}
+
+fn disassemble(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let rom = std::fs::read(&options.rom).map_err(|err| format!("failed to load ROM '{}': {}", options.rom, err))?;
+
+    let ram = Ram::with_size(options.quirks.memory_size());
+    let mut cpu = Cpu::new(ram, HeadlessDisplay::new(), ScriptedKeypad::new(), NullAudio);
+
+    cpu.load_rom(&rom).map_err(|err| format!("failed to load ROM '{}': {}", options.rom, err))?;
+    // only the ROM itself, not the empty memory after it
+    cpu.disas(rom.len());
+
+    Ok(())
+}
+
+fn run(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let rom = std::fs::read(&options.rom).map_err(|err| format!("failed to load ROM '{}': {}", options.rom, err))?;
+
+    // a movie brings along everything the run depends on besides input
+    let played = match &options.movie {
+        Some(MovieOption::Play(path)) => {
+            let bytes = std::fs::read(path).map_err(|err| format!("failed to read movie '{}': {}", path, err))?;
+            let movie = Movie::from_bytes(&bytes).map_err(|err| format!("failed to read movie '{}': {}", path, err))?;
+            if movie.header.rom_hash != rom_hash(&rom) {
+                return Err(format!("movie '{}' was recorded with a different ROM", path).into());
+            }
+            Some(movie)
+        }
+        _ => None,
+    };
+    let header = match &played {
+        Some(movie) => movie.header.clone(),
+        None => MovieHeader {
+            rom_hash: rom_hash(&rom),
+            quirks: options.quirks,
+            cycles_per_frame: options.ipf,
+            seed: options.seed.unwrap_or_else(rand::random),
+        },
+    };
+
+    let keymap_config = match &options.keymap {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).map_err(|err| format!("failed to read keymap '{}': {}", path, err))?;
+            KeymapConfig::parse(&text).map_err(|err| format!("invalid keymap '{}': {}", path, err))?
+        }
+        None => KeymapConfig::default(),
+    };
+
+    let sdl_context = sdl2::init()?;
+
+    let display = SdlDisplay::new(&sdl_context, options.scale, options.vsync);
+    let sdl_keypad = SdlKeypad::new(&sdl_context, keymap_config.keymap(&options.rom))?;
+    let hotkeys = sdl_keypad.hotkeys();
+    let keypad = match (&options.movie, played) {
+        (Some(MovieOption::Record(_)), _) => MovieKeypad::record(sdl_keypad, header.clone()),
+        (_, Some(movie)) => MovieKeypad::play(sdl_keypad, movie),
+        _ => MovieKeypad::passthrough(sdl_keypad),
+    };
+
+    let (audio, muted): (Box<dyn Audio>, _) = match SdlAudio::new(&sdl_context, options.tone, options.mute) {
+        Ok(beeper) => {
+            let muted = beeper.mute_handle();
+            (Box::new(beeper), Some(muted))
+        }
+        Err(err) => {
+            eprintln!("warning: audio unavailable, running without sound: {}", err);
+            (Box::new(NullAudio), None)
+        }
+    };
+
+    let mut chip8 = Chip8::with_backends(header.quirks, display, keypad, audio);
+    let cpu = chip8.cpu_mut();
+    cpu.set_cycles_per_frame(header.cycles_per_frame);
+    cpu.set_rng(Box::new(SeededRng::new(header.seed)));
+    // rewinding would throw a recording or playback out of step with the movie
+    if options.movie.is_none() {
+        cpu.set_rewind_budget(options.rewind_mb as usize * 1024 * 1024);
+    }
+
+    chip8.load_rom(&rom).map_err(|err| format!("failed to load ROM '{}': {}", options.rom, err))?;
+
+    println!(
+        "Running {} at {} instructions per frame ({} quirks, seed {})",
+        options.rom,
+        header.cycles_per_frame,
+        header.quirks,
+        header.seed
+    );
+
+    let mut tracer = match &options.trace {
+        Some(path) => {
+            let file = File::create(path).map_err(|err| format!("failed to create trace '{}': {}", path, err))?;
+            Some(Tracer::new(BufWriter::new(file), options.trace_format, options.trace_filter.clone()))
+        }
+        None => None,
+    };
 
-    let mut display = Display::new(&sdl_context, 20);
-    let mut keypad = Keypad::new(&sdl_context);
-    let mut ram = Ram::new();
-    let mut cpu = Cpu::new(&mut ram, &mut display, &mut keypad);
+    let result = run_loop(&mut chip8, options, &hotkeys, muted.as_deref(), tracer.as_mut());
 
-    //cpu.load_rom_into_ram("roms/keypad.ch8").expect("Failed to load CPU rom");
-    cpu.load_rom_into_ram("roms/invaders.ch8").expect("Failed to load CPU rom");
+    if let (Some(tracer), Some(path)) = (tracer, &options.trace) {
+        tracer.finish().map_err(|err| format!("failed to write trace '{}': {}", path, err))?;
+    }
+
+    // keep whatever was recorded, even if the ROM crashed
+    if let Some(MovieOption::Record(path)) = &options.movie {
+        let (_, _, keypad, _) = chip8.into_cpu().into_parts();
+        let movie = keypad.into_movie().expect("keypad is recording");
+        std::fs::write(path, movie.to_bytes()).map_err(|err| format!("failed to save movie '{}': {}", path, err))?;
+        println!("Recorded {} frames to {}", movie.frames(), path);
+    }
+
+    result
+}
 
-    cpu.disas();
-    for _ in 0..5 {
-        println!();
+// emulated frames run per frame of real time while fast forwarding
+const TURBO_SPEED: usize = 4;
+
+fn run_loop(
+    chip8: &mut Chip8<SdlDisplay, MovieKeypad<SdlKeypad>, Box<dyn Audio>>,
+    options: &Options,
+    hotkeys: &Rc<RefCell<VecDeque<Hotkey>>>,
+    muted: Option<&AtomicBool>,
+    mut tracer: Option<&mut Tracer<BufWriter<File>>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut debugger = Debugger::new();
+    let mut debugging = options.mode == Mode::Debug;
+    let mut step_over = None;
+    if debugging {
+        println!("Debugger attached, type 'help' for commands. Press F12 in the window to break.");
     }
 
+    // Each pass of the loop is one frame of real time, paced to 60 per second.
+    // The emulated clock only advances with executed instructions, so timers
+    // tick once per emulated frame however fast the host runs them.
+    let frame_duration = Duration::from_nanos(1_000_000_000 / 60);
+    let mut next_frame = Instant::now() + frame_duration;
+    let mut rewinding = false;
+    let mut paused = false;
+    let mut turbo = false;
     loop {
-        cpu.tick();
-        std::thread::sleep(std::time::Duration::new(0, 1_000_000_000 / 500));
+        // the buzzer stays quiet while the machine isn't running
+        if debugging {
+            chip8.cpu_mut().audio_mut().set_beeping(false);
+            match repl::prompt(chip8.cpu_mut(), &mut debugger, tracer.as_deref_mut()) {
+                Resume::Continue => step_over = None,
+                Resume::StepOver { pc, sp } => step_over = Some((pc, sp)),
+                Resume::Quit => return Ok(()),
+            }
+            debugging = false;
+        }
+
+        if rewinding || paused {
+            chip8.cpu_mut().audio_mut().set_beeping(false);
+            // one emulated frame back per frame of real time
+            if rewinding {
+                chip8.cpu_mut().rewind(1);
+            }
+            if let Err(EmuError::UserQuit) = chip8.cpu_mut().poll_input() {
+                return Ok(());
+            }
+        } else {
+            // and picks up again from the sound timer when it resumes
+            let beeping = chip8.sound_active();
+            chip8.cpu_mut().audio_mut().set_beeping(beeping);
+
+            let speed = if turbo { TURBO_SPEED } else { 1 };
+            for _ in 0..speed {
+                let result = chip8.run_frame_with(|cpu| {
+                    let outcome = match tracer.as_deref_mut() {
+                        Some(tracer) => tracer.step(cpu)?,
+                        None => cpu.tick()?,
+                    };
+
+                    if options.mode == Mode::Debug {
+                        if let Some(reason) = debugger.check(cpu) {
+                            println!("Stopped: {}", reason);
+                            return Ok(None);
+                        } else if step_over == Some((cpu.pc(), cpu.sp())) {
+                            return Ok(None);
+                        }
+                    }
+
+                    Ok(Some(outcome))
+                });
+
+                match result {
+                    Ok(Some(StepOutcome::Exited)) | Err(EmuError::UserQuit) => return Ok(()),
+                    Ok(Some(_)) => {}
+                    // stopped at a breakpoint, watchpoint or the end of a step over
+                    Ok(None) => {
+                        debugging = true;
+                        break;
+                    }
+                    Err(err) if options.mode == Mode::Debug => {
+                        println!("Error: {}", err);
+                        debugging = true;
+                        break;
+                    }
+                    Err(err) => return Err(format!("'{}' crashed: {}", options.rom, err).into()),
+                }
+            }
+        }
+
+        while let Some(hotkey) = hotkeys.borrow_mut().pop_front() {
+            match hotkey {
+                Hotkey::Break => debugging |= options.mode == Mode::Debug,
+                Hotkey::ToggleMute => {
+                    if let Some(muted) = muted {
+                        muted.fetch_xor(true, Ordering::Relaxed);
+                    }
+                }
+                Hotkey::Pause => {
+                    paused = !paused;
+                    println!("{}", if paused { "Paused" } else { "Resumed" });
+                }
+                Hotkey::Turbo(held) => turbo = held,
+                Hotkey::SaveState(slot) => {
+                    let path = state_path(&options.rom, slot);
+                    match std::fs::write(&path, chip8.cpu_mut().save_state()) {
+                        Ok(()) => println!("Saved state to {}", path),
+                        Err(err) => eprintln!("error: failed to save state to {}: {}", path, err),
+                    }
+                }
+                Hotkey::Rewind(true) if options.movie.is_some() => {
+                    eprintln!("error: can't rewind while recording or playing a movie");
+                }
+                Hotkey::Rewind(held) => rewinding = held,
+                Hotkey::LoadState(_) if options.movie.is_some() => {
+                    eprintln!("error: can't load a state while recording or playing a movie");
+                }
+                Hotkey::LoadState(slot) => {
+                    let path = state_path(&options.rom, slot);
+                    let result = std::fs::read(&path)
+                        .map_err(|err| err.to_string())
+                        .and_then(|bytes| chip8.cpu_mut().load_state(&bytes).map_err(|err| err.to_string()));
+                    match result {
+                        Ok(()) => println!("Loaded state from {}", path),
+                        Err(err) => eprintln!("error: failed to load state from {}: {}", path, err),
+                    }
+                }
+            }
+        }
+
+        // with --vsync this waits for the monitor, leaving less time to sleep
+        chip8.cpu_mut().display_mut().redraw();
+
+        let now = Instant::now();
+        if next_frame > now {
+            std::thread::sleep(next_frame - now);
+        } else if now - next_frame > frame_duration {
+            // fell behind, e.g. after sitting in the debugger, so don't try to catch up
+            next_frame = now;
+        }
+        next_frame += frame_duration;
+    }
+}
+
+fn main() {
+    let options = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(CliError::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = match options.mode {
+        Mode::Disassemble => disassemble(&options),
+        Mode::Run | Mode::Debug => run(&options),
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
     }
 }
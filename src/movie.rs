@@ -0,0 +1,421 @@
+use std::fmt;
+
+use crate::error::EmuError;
+use crate::keypad::{Keypad, KeypadState};
+use crate::quirks::QuirkProfile;
+use crate::state::{Reader, StateError};
+
+const MAGIC: &[u8; 4] = b"CH8M";
+pub const VERSION: u16 = 2;
+
+// A checkpoint hash of memory and the screen is recorded every this many frames
+pub const CHECKPOINT_INTERVAL: u64 = 60;
+
+#[derive(Debug)]
+pub enum MovieError {
+    BadMagic,
+    UnsupportedVersion { found: u16, expected: u16 },
+    Truncated,
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "not a movie file"),
+            MovieError::UnsupportedVersion { found, expected } => {
+                write!(f, "movie version {} is not supported (expected version {})", found, expected)
+            }
+            MovieError::Truncated => write!(f, "movie is truncated"),
+            MovieError::Corrupt(reason) => write!(f, "movie is corrupt: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<StateError> for MovieError {
+    fn from(err: StateError) -> Self {
+        match err {
+            StateError::Truncated => MovieError::Truncated,
+            _ => MovieError::Corrupt("unreadable data"),
+        }
+    }
+}
+
+// Everything besides input that a run depends on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovieHeader {
+    pub rom_hash: u64,
+    pub quirks: QuirkProfile,
+    pub cycles_per_frame: u32,
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieEvent {
+    // the key changed state at the end of the frame
+    Key { frame: u64, key: u8, pressed: bool },
+    Checkpoint { frame: u64, hash: u64 },
+    // recording stopped during this frame
+    End { frame: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub header: MovieHeader,
+    pub events: Vec<MovieEvent>,
+}
+
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+impl Movie {
+    pub fn new(header: MovieHeader) -> Self {
+        Movie { header, events: Vec::new() }
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.events.last().map_or(0, |event| match *event {
+            MovieEvent::Key { frame, .. }
+            | MovieEvent::Checkpoint { frame, .. }
+            | MovieEvent::End { frame } => frame,
+        })
+    }
+
+    // Layout (all integers big endian):
+    //   magic "CH8M", version u16,
+    //   rom hash u64, quirk profile name length u8, name, cycles per frame u32, seed u64,
+    //   event count u32, then per event a kind u8 and frame u64 followed by
+    //   key u8 and pressed u8 (kind 0), hash u64 (kind 2) or nothing (kind 3).
+    //   Kind 1 was a key returned by Fx0A, which version 1 recorded separately.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(64 + 18 * self.events.len());
+
+        res.extend_from_slice(MAGIC);
+        res.extend_from_slice(&VERSION.to_be_bytes());
+
+        let quirks = self.header.quirks.to_string();
+        res.extend_from_slice(&self.header.rom_hash.to_be_bytes());
+        res.push(quirks.len() as u8);
+        res.extend_from_slice(quirks.as_bytes());
+        res.extend_from_slice(&self.header.cycles_per_frame.to_be_bytes());
+        res.extend_from_slice(&self.header.seed.to_be_bytes());
+
+        res.extend_from_slice(&(self.events.len() as u32).to_be_bytes());
+        for event in self.events.iter() {
+            match *event {
+                MovieEvent::Key { frame, key, pressed } => {
+                    res.push(0);
+                    res.extend_from_slice(&frame.to_be_bytes());
+                    res.push(key);
+                    res.push(u8::from(pressed));
+                }
+                MovieEvent::Checkpoint { frame, hash } => {
+                    res.push(2);
+                    res.extend_from_slice(&frame.to_be_bytes());
+                    res.extend_from_slice(&hash.to_be_bytes());
+                }
+                MovieEvent::End { frame } => {
+                    res.push(3);
+                    res.extend_from_slice(&frame.to_be_bytes());
+                }
+            }
+        }
+
+        res
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, MovieError> {
+        let mut reader = Reader { bytes };
+
+        if reader.take(4).map_err(|_| MovieError::BadMagic)? != MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion { found: version, expected: VERSION });
+        }
+
+        let rom_hash = reader.u64()?;
+        let quirks_len = reader.u8()?;
+        let quirks = std::str::from_utf8(reader.take(usize::from(quirks_len))?)
+            .ok()
+            .and_then(|name| name.parse().ok())
+            .ok_or(MovieError::Corrupt("unknown quirk profile"))?;
+        let cycles_per_frame = reader.u32()?;
+        if cycles_per_frame == 0 {
+            return Err(MovieError::Corrupt("no cycles per frame"));
+        }
+        let seed = reader.u64()?;
+
+        let count = reader.u32()?;
+        let mut events = Vec::new();
+        let mut last_frame = 0;
+        for _ in 0..count {
+            let kind = reader.u8()?;
+            let frame = reader.u64()?;
+            let event = match kind {
+                0 => MovieEvent::Key { frame, key: reader.u8()?, pressed: reader.u8()? != 0 },
+                2 => MovieEvent::Checkpoint { frame, hash: reader.u64()? },
+                3 => MovieEvent::End { frame },
+                _ => return Err(MovieError::Corrupt("unknown event")),
+            };
+
+            match event {
+                MovieEvent::Key { key, .. } if key > 0xF => {
+                    return Err(MovieError::Corrupt("key out of range"));
+                }
+                _ if frame < last_frame => return Err(MovieError::Corrupt("events out of order")),
+                _ => {}
+            }
+
+            last_frame = frame;
+            events.push(event);
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(MovieError::Corrupt("trailing data"));
+        }
+
+        Ok(Movie {
+            header: MovieHeader { rom_hash, quirks, cycles_per_frame, seed },
+            events,
+        })
+    }
+}
+
+enum Mode {
+    Passthrough,
+    Recording(Movie),
+    Playing { movie: Movie, next: usize },
+}
+
+// Wraps the real keypad. The Cpu polls once per frame, so recording the
+// changes seen by each poll is enough for a replay to see exactly what the
+// recording saw. Once playback runs out of events, input comes from the real
+// keypad again.
+pub struct MovieKeypad<K: Keypad> {
+    inner: K,
+    mode: Mode,
+    pressed: KeypadState,
+    frame: u64,
+}
+
+impl<K: Keypad> MovieKeypad<K> {
+    pub fn passthrough(inner: K) -> Self {
+        MovieKeypad { inner, mode: Mode::Passthrough, pressed: KeypadState::default(), frame: 0 }
+    }
+
+    pub fn record(inner: K, header: MovieHeader) -> Self {
+        MovieKeypad { inner, mode: Mode::Recording(Movie::new(header)), pressed: KeypadState::default(), frame: 0 }
+    }
+
+    pub fn play(inner: K, movie: Movie) -> Self {
+        MovieKeypad { inner, mode: Mode::Playing { movie, next: 0 }, pressed: KeypadState::default(), frame: 0 }
+    }
+
+    pub fn inner_mut(&mut self) -> &mut K {
+        &mut self.inner
+    }
+
+    pub fn is_playing(&self) -> bool {
+        match &self.mode {
+            Mode::Playing { movie, next } => *next < movie.events.len(),
+            _ => false,
+        }
+    }
+
+    pub fn into_movie(self) -> Option<Movie> {
+        match self.mode {
+            Mode::Recording(mut movie) => {
+                movie.events.push(MovieEvent::End { frame: self.frame });
+                Some(movie)
+            }
+            Mode::Playing { movie, .. } => Some(movie),
+            Mode::Passthrough => None,
+        }
+    }
+}
+
+impl<K: Keypad> Keypad for MovieKeypad<K> {
+    // The inner keypad is polled while playing too, so it keeps noticing
+    // quit requests and hotkeys
+    fn poll(&mut self) -> KeypadState {
+        let live = self.inner.poll();
+        let playing = self.is_playing();
+
+        match &mut self.mode {
+            Mode::Recording(movie) => {
+                let changed = KeypadState::from_bits(live.bits() ^ self.pressed.bits());
+                for key in changed.pressed_keys() {
+                    movie.events.push(MovieEvent::Key { frame: self.frame, key, pressed: live.is_pressed(key) });
+                }
+                self.pressed = live;
+            }
+            Mode::Playing { .. } if playing => {}
+            // passing through, or the recording is over and input is live again
+            _ => self.pressed = live,
+        }
+
+        self.pressed
+    }
+
+    fn check_for_exit(&mut self) -> bool {
+        self.inner.check_for_exit()
+    }
+
+    fn end_frame(&mut self, frame: u64, state_hash: &dyn Fn() -> u64) -> Result<(), EmuError> {
+        self.frame = frame;
+
+        match &mut self.mode {
+            Mode::Passthrough => {}
+            Mode::Recording(movie) => {
+                if frame % CHECKPOINT_INTERVAL == 0 {
+                    movie.events.push(MovieEvent::Checkpoint { frame, hash: state_hash() });
+                }
+            }
+            Mode::Playing { movie, next } => {
+                while let Some(&event) = movie.events.get(*next) {
+                    match event {
+                        MovieEvent::Key { frame: at, key, pressed } if at <= frame => {
+                            self.pressed.set(key, pressed);
+                        }
+                        MovieEvent::Checkpoint { frame: at, hash } if at <= frame => {
+                            let found = state_hash();
+                            if found != hash {
+                                return Err(EmuError::MovieDesync { frame, expected: hash, found });
+                            }
+                        }
+                        MovieEvent::End { frame: at } if at <= frame => {}
+                        _ => break,
+                    }
+                    *next += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::audio::NullAudio;
+    use crate::cpu::Cpu;
+    use crate::display::HeadlessDisplay;
+    use crate::keypad::ScriptedKeypad;
+    use crate::ram::Ram;
+    use crate::rng::SeededRng;
+    use crate::state::SaveState;
+
+    fn header() -> MovieHeader {
+        MovieHeader { rom_hash: rom_hash(b"rom"), quirks: QuirkProfile::SuperChip, cycles_per_frame: 10, seed: 99 }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut movie = Movie::new(header());
+        movie.events.push(MovieEvent::Key { frame: 1, key: 0xA, pressed: true });
+        movie.events.push(MovieEvent::Key { frame: 3, key: 0x2, pressed: false });
+        movie.events.push(MovieEvent::Checkpoint { frame: 60, hash: 0x1234_5678_9ABC_DEF0 });
+        movie.events.push(MovieEvent::End { frame: 62 });
+
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+        assert_eq!(movie.frames(), 62);
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut movie = Movie::new(header());
+        movie.events.push(MovieEvent::Key { frame: 5, key: 0x1, pressed: true });
+        movie.events.push(MovieEvent::Key { frame: 4, key: 0x1, pressed: false });
+        assert!(matches!(Movie::from_bytes(&movie.to_bytes()), Err(MovieError::Corrupt(_))));
+
+        let bytes = Movie::new(header()).to_bytes();
+        assert!(matches!(Movie::from_bytes(&bytes[..bytes.len() - 1]), Err(MovieError::Truncated)));
+        assert!(matches!(Movie::from_bytes(b"CH8S\0\x01"), Err(MovieError::BadMagic)));
+    }
+
+    #[test]
+    fn test_records_changes_seen_by_each_poll() {
+        let mut keypad = MovieKeypad::record(ScriptedKeypad::new(), header());
+
+        keypad.inner_mut().press(0x5);
+        keypad.inner_mut().press(0x6);
+        keypad.inner_mut().release(0x6);
+        keypad.end_frame(1, &|| 0).unwrap();
+        assert_eq!(keypad.poll().bits(), 1 << 0x5);
+
+        keypad.inner_mut().release(0x5);
+        keypad.end_frame(2, &|| 0).unwrap();
+        keypad.poll();
+        keypad.end_frame(CHECKPOINT_INTERVAL, &|| 7).unwrap();
+        keypad.poll();
+
+        let events = keypad.into_movie().unwrap().events;
+        assert_eq!(events, vec![
+            MovieEvent::Key { frame: 1, key: 0x5, pressed: true },
+            MovieEvent::Key { frame: 2, key: 0x5, pressed: false },
+            MovieEvent::Checkpoint { frame: CHECKPOINT_INTERVAL, hash: 7 },
+            MovieEvent::End { frame: CHECKPOINT_INTERVAL },
+        ]);
+    }
+
+    // Waits for a key, then keeps counting into V1 while key 1 is held and
+    // drawing the glyph of the key that was waited for.
+    const PROGRAM: [u16; 7] = [0xF00A, 0x6201, 0xE2A1, 0x7101, 0xF029, 0xD005, 0x1202];
+
+    // Runs PROGRAM, changing keys on the inner keypad before the given ticks
+    fn run(keypad: MovieKeypad<ScriptedKeypad>, ticks: usize, keys: &[(usize, u8, bool)]) -> (Result<Vec<u8>, EmuError>, MovieKeypad<ScriptedKeypad>) {
+        let mut ram = Ram::new();
+
+        for (i, &op) in PROGRAM.iter().enumerate() {
+            ram.set(0x200 + 2 * i as u16, (op >> 8) as u8);
+            ram.set(0x201 + 2 * i as u16, op as u8);
+        }
+
+        let mut cpu = Cpu::new(ram, HeadlessDisplay::new(), keypad, NullAudio);
+        cpu.set_cycles_per_frame(5);
+        cpu.set_rng(Box::new(SeededRng::new(header().seed)));
+        let res = (0..ticks).try_for_each(|tick| {
+            for &(_, key, pressed) in keys.iter().filter(|&&(at, _, _)| at == tick) {
+                let inner = cpu.keypad_mut().inner_mut();
+                if pressed { inner.press(key) } else { inner.release(key) }
+            }
+            cpu.tick().map(|_| ())
+        });
+
+        let state = cpu.save_state();
+        let (_, _, keypad, _) = cpu.into_parts();
+        (res.map(|()| state), keypad)
+    }
+
+    #[test]
+    fn test_playback_reproduces_recording() {
+        let ticks = 5 * CHECKPOINT_INTERVAL as usize + 3;
+        let keys = [(0, 0x7, true), (20, 0x7, false), (40, 0x1, true)];
+
+        let (recorded, recorder) = run(MovieKeypad::record(ScriptedKeypad::new(), header()), ticks, &keys);
+        let recorded = recorded.unwrap();
+        let state = SaveState::from_bytes(&recorded).unwrap();
+        assert_eq!(state.regs[0], 0x7);
+        assert!(state.regs[1] > 0);
+        let movie = recorder.into_movie().unwrap();
+        assert!(movie.events.contains(&MovieEvent::Key { frame: 1, key: 0x7, pressed: true }));
+
+        let player = MovieKeypad::play(ScriptedKeypad::new(), movie.clone());
+        assert_eq!(run(player, ticks, &[]).0.unwrap(), recorded);
+
+        let mut tampered = movie;
+        if let Some(MovieEvent::Checkpoint { hash, .. }) = tampered.events.iter_mut().find(|event| matches!(event, MovieEvent::Checkpoint { .. })) {
+            *hash ^= 1;
+        }
+        let player = MovieKeypad::play(ScriptedKeypad::new(), tampered);
+        assert!(matches!(run(player, ticks, &[]).0, Err(EmuError::MovieDesync { .. })));
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::instruction::InstructionSet;
+use crate::ram::{RAM_SIZE, XO_CHIP_RAM_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    pub shift_uses_vy: bool,           // 8xy6/8xyE shift Vy into Vx instead of shifting Vx in place
+    pub load_store_increments_i: bool, // Fx55/Fx65 leave I pointing past the last register
+    pub jump_uses_vx: bool,            // Bnnn jumps to nnn + Vx (x being the high nibble) instead of nnn + V0
+    pub clip_sprites: bool,            // sprites are clipped at the screen edges instead of wrapping
+    pub vf_reset: bool,                // 8xy1/8xy2/8xy3 clear VF
+    pub display_wait: bool,            // Dxyn waits for the next 60 Hz frame if a sprite was already drawn
+    pub instruction_set: InstructionSet, // opcodes from later machines are invalid
+}
+
+impl Quirks {
+    pub fn vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            clip_sprites: true,
+            vf_reset: true,
+            display_wait: true,
+            instruction_set: InstructionSet::Chip8,
+        }
+    }
+
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+            instruction_set: InstructionSet::Chip8,
+        }
+    }
+
+    pub fn superchip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+            instruction_set: InstructionSet::SuperChip,
+        }
+    }
+
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            vf_reset: false,
+            display_wait: false,
+            instruction_set: InstructionSet::XoChip,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::vip()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuirkProfile {
+    Vip,
+    Chip48,
+    SuperChip,
+    XoChip,
+}
+
+impl QuirkProfile {
+    pub fn quirks(self) -> Quirks {
+        match self {
+            QuirkProfile::Vip => Quirks::vip(),
+            QuirkProfile::Chip48 => Quirks::chip48(),
+            QuirkProfile::SuperChip => Quirks::superchip(),
+            QuirkProfile::XoChip => Quirks::xochip(),
+        }
+    }
+
+    pub fn memory_size(self) -> usize {
+        match self {
+            QuirkProfile::XoChip => XO_CHIP_RAM_SIZE,
+            _ => RAM_SIZE,
+        }
+    }
+}
+
+impl FromStr for QuirkProfile {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac" => Ok(QuirkProfile::Vip),
+            "chip48" | "chip-48" => Ok(QuirkProfile::Chip48),
+            "schip" | "superchip" | "super-chip" => Ok(QuirkProfile::SuperChip),
+            "xochip" | "xo-chip" => Ok(QuirkProfile::XoChip),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for QuirkProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QuirkProfile::Vip => write!(f, "vip"),
+            QuirkProfile::Chip48 => write!(f, "chip48"),
+            QuirkProfile::SuperChip => write!(f, "schip"),
+            QuirkProfile::XoChip => write!(f, "xochip"),
+        }
+    }
+}
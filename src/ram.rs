@@ -1,22 +1,63 @@
 const FONT_SET: [u8; 80] = [0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70, 0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0, 0x10, 0xF0, 0x10, 0xF0, 0x90, 0x90, 0xF0, 0x10, 0x10, 0xF0, 0x80, 0xF0, 0x10, 0xF0, 0xF0, 0x80, 0xF0, 0x90, 0xF0, 0xF0, 0x10, 0x20, 0x40, 0x40, 0xF0, 0x90, 0xF0, 0x90, 0xF0, 0xF0, 0x90, 0xF0, 0x10, 0xF0, 0xF0, 0x90, 0xF0, 0x90, 0x90, 0xE0, 0x90, 0xE0, 0x90, 0xE0, 0xF0, 0x80, 0x80, 0x80, 0xF0, 0xE0, 0x90, 0x90, 0x90, 0xE0, 0xF0, 0x80, 0xF0, 0x80, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0x80];
 
+// SUPER-CHIP 8x10 digits, with A-F as drawn by Octo
+const BIG_FONT_SET: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+pub const FONT_ADDR: u16 = 0x0;
+pub const BIG_FONT_ADDR: u16 = 0x50;
+
+pub const RAM_SIZE: usize = 0x1000;
+pub const XO_CHIP_RAM_SIZE: usize = 0x10000;
+
+#[derive(Clone)]
 pub struct Ram {
-    ram: [u8; 0x1000],
+    ram: Vec<u8>,
 }
 
 impl Ram {
     pub fn new() -> Ram {
+        Ram::with_size(RAM_SIZE)
+    }
+
+    pub fn with_size(size: usize) -> Ram {
+        assert!((0x200..=XO_CHIP_RAM_SIZE).contains(&size));
+
         let mut res = Ram {
-            ram: [0; 0x1000],
+            ram: vec![0; size],
         };
 
         for i in 0..80 {
-            res.set(i, FONT_SET[usize::from(i)]);
+            res.set(FONT_ADDR + i, FONT_SET[usize::from(i)]);
+        };
+
+        for i in 0..160 {
+            res.set(BIG_FONT_ADDR + i, BIG_FONT_SET[usize::from(i)]);
         };
 
         res
     }
 
+    pub fn size(&self) -> usize {
+        self.ram.len()
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
         self.ram[usize::from(addr)]
     }
@@ -26,6 +67,12 @@ impl Ram {
     }
 }
 
+impl Default for Ram {
+    fn default() -> Self {
+        Ram::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::convert::TryFrom;
@@ -53,12 +100,26 @@ mod tests {
         assert_eq!(ram.read(0x400), 0x12);
     }
 
+    #[test]
+    fn test_xo_chip_size() {
+        let mut ram = Ram::with_size(XO_CHIP_RAM_SIZE);
+
+        assert_eq!(ram.size(), 0x10000);
+        ram.set(0xFFFF, 0x12);
+        assert_eq!(ram.read(0xFFFF), 0x12);
+        assert_eq!(ram.read(FONT_ADDR), FONT_SET[0]);
+    }
+
     #[test]
     fn test_font_init() {
         let ram = Ram::new();
 
         for (i, &val) in FONT_SET.iter().enumerate() {
-            assert_eq!(ram.read(u16::try_from(i).unwrap()), val);
+            assert_eq!(ram.read(FONT_ADDR + u16::try_from(i).unwrap()), val);
+        }
+
+        for (i, &val) in BIG_FONT_SET.iter().enumerate() {
+            assert_eq!(ram.read(BIG_FONT_ADDR + u16::try_from(i).unwrap()), val);
         }
     }
 }
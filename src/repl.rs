@@ -0,0 +1,163 @@
+use std::io::{self, BufRead, Write};
+
+use emulator::audio::Audio;
+use emulator::cpu::Cpu;
+use emulator::debugger::{Command, Debugger};
+use emulator::display::Display;
+use emulator::error::EmuError;
+use emulator::keypad::Keypad;
+use emulator::trace::Tracer;
+
+pub enum Resume {
+    Continue,
+    StepOver { pc: u16, sp: u8 },
+    Quit,
+}
+
+#[derive(Debug, PartialEq)]
+enum Stepped {
+    Ok,
+    Stopped,
+    Quit,
+}
+
+// Steps through the tracer when there is one, so single steps end up in the trace too
+fn step<D: Display, K: Keypad, A: Audio, W: Write>(
+    cpu: &mut Cpu<D, K, A>,
+    debugger: &mut Debugger,
+    tracer: Option<&mut Tracer<W>>,
+) -> Stepped {
+    let result = match tracer {
+        Some(tracer) => tracer.step(cpu),
+        None => cpu.tick(),
+    }
+    .map(|_| debugger.check(cpu));
+    // the main loop isn't running frames while the debugger has control, so
+    // show the step and don't leave the buzzer going between steps
+    cpu.display_mut().redraw();
+    cpu.audio_mut().set_beeping(false);
+
+    match result {
+        Ok(None) => Stepped::Ok,
+        Ok(Some(reason)) => {
+            println!("Stopped: {}", reason);
+            Stepped::Stopped
+        }
+        Err(EmuError::UserQuit) => Stepped::Quit,
+        Err(err) => {
+            println!("Error: {}", err);
+            Stepped::Stopped
+        }
+    }
+}
+
+// Reads debugger commands from stdin until one of them resumes execution.
+pub fn prompt<D: Display, K: Keypad, A: Audio, W: Write>(
+    cpu: &mut Cpu<D, K, A>,
+    debugger: &mut Debugger,
+    mut tracer: Option<&mut Tracer<W>>,
+) -> Resume {
+    let stdin = io::stdin();
+
+    loop {
+        print!("(chip8 0x{:04x}) ", cpu.pc());
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => return Resume::Quit,
+            Ok(_) => {}
+        }
+
+        let command = match Command::parse(&line) {
+            Ok(command) => command,
+            Err(err) => {
+                println!("{}", err);
+                continue;
+            }
+        };
+
+        match command {
+            Command::Break(addr) | Command::Watch(addr) | Command::Memory { addr, .. }
+                if usize::from(addr) >= cpu.memory_size() =>
+            {
+                println!("Address 0x{:04x} is outside of memory", addr);
+            }
+            Command::Step(count) => {
+                for _ in 0..count {
+                    match step(cpu, debugger, tracer.as_deref_mut()) {
+                        Stepped::Ok => {}
+                        Stepped::Stopped => break,
+                        Stepped::Quit => return Resume::Quit,
+                    }
+                }
+            }
+            Command::Next => match Debugger::step_over_target(cpu) {
+                Ok(Some((pc, sp))) => return Resume::StepOver { pc, sp },
+                Ok(None) => {
+                    if let Stepped::Quit = step(cpu, debugger, tracer.as_deref_mut()) {
+                        return Resume::Quit;
+                    }
+                }
+                Err(err) => println!("Error: {}", err),
+            },
+            Command::Continue => return Resume::Continue,
+            Command::Break(addr) => debugger.add_breakpoint(addr),
+            Command::Watch(addr) => debugger.add_watchpoint(cpu, addr),
+            Command::Delete(addr) => {
+                if !debugger.remove(addr) {
+                    println!("No breakpoint or watchpoint at 0x{:04x}", addr);
+                }
+            }
+            Command::List => {
+                for addr in debugger.breakpoints() {
+                    println!("breakpoint 0x{:04x}", addr);
+                }
+                for addr in debugger.watchpoints() {
+                    println!("watchpoint 0x{:04x}", addr);
+                }
+            }
+            Command::Registers => println!("{}", Debugger::format_registers(cpu)),
+            Command::Memory { addr, len } => println!("{}", Debugger::format_memory(cpu, addr, len)),
+            Command::Help => println!("{}", emulator::debugger::HELP),
+            Command::Quit => return Resume::Quit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use emulator::audio::NullAudio;
+    use emulator::display::HeadlessDisplay;
+    use emulator::keypad::ScriptedKeypad;
+    use emulator::ram::Ram;
+    use emulator::trace::{TraceFilter, TraceFormat};
+
+    #[test]
+    fn test_step() {
+        // LD V0, 0x07; LD I, 0x300; LD [I], V0; JP 0xFFF
+        let mut cpu = Cpu::new(Ram::new(), HeadlessDisplay::new(), ScriptedKeypad::new(), NullAudio);
+        cpu.load_rom(&[0x60, 0x07, 0xA3, 0x00, 0xF0, 0x55, 0x1F, 0xFF]).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x204);
+        debugger.add_watchpoint(&cpu, 0x300);
+        let mut tracer = Tracer::new(Vec::new(), TraceFormat::Csv, TraceFilter::default());
+
+        assert_eq!(step(&mut cpu, &mut debugger, Some(&mut tracer)), Stepped::Ok);
+        assert_eq!(step(&mut cpu, &mut debugger, Some(&mut tracer)), Stepped::Stopped);
+        assert_eq!(step(&mut cpu, &mut debugger, None::<&mut Tracer<Vec<u8>>>), Stepped::Stopped);
+        assert_eq!(cpu.peek(0x300), 0x07);
+
+        // steps taken with the tracer show up in the trace, after its header
+        let trace = String::from_utf8(tracer.finish().unwrap()).unwrap();
+        assert_eq!(trace.lines().count(), 3);
+
+        // an error stops stepping but leaves the machine where it was
+        assert_eq!(step(&mut cpu, &mut debugger, None::<&mut Tracer<Vec<u8>>>), Stepped::Ok);
+        assert_eq!(step(&mut cpu, &mut debugger, None::<&mut Tracer<Vec<u8>>>), Stepped::Stopped);
+        assert_eq!(cpu.pc(), 0xFFF);
+        assert_eq!(Debugger::step_over_target(&cpu), Err(EmuError::MemoryOutOfBounds { pc: 0xFFF, addr: 0x1000 }));
+    }
+}
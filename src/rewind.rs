@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+
+// Every delta is a list of runs: offset u32, length u16, then the bytes the
+// older state had there (all integers big endian).
+const RUN_HEADER_LEN: usize = 6;
+
+// Keeps recent history as serialized save states. Only the newest state is
+// stored whole, each older frame is a delta that turns the state after it
+// back into that frame. The oldest deltas are dropped to stay within budget.
+#[derive(Clone)]
+pub struct RewindBuffer {
+    budget: usize,
+    current: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    used: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(budget: usize) -> Self {
+        RewindBuffer { budget, current: None, deltas: VecDeque::new(), used: 0 }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    // Bytes held, including the newest full state
+    pub fn memory_used(&self) -> usize {
+        self.used + self.current.as_ref().map_or(0, Vec::len)
+    }
+
+    // How many frames back rewind can go
+    pub fn frames(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn current(&self) -> Option<&[u8]> {
+        self.current.as_deref()
+    }
+
+    pub fn clear(&mut self) {
+        self.current = None;
+        self.deltas.clear();
+        self.used = 0;
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        match self.current.take() {
+            Some(previous) if previous.len() == state.len() => {
+                let delta = diff(&state, &previous);
+                self.used += delta.len();
+                self.deltas.push_back(delta);
+            }
+            _ => self.clear(),
+        }
+        self.current = Some(state);
+
+        while self.memory_used() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.used -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    // Steps back up to `frames` frames and returns how many it managed
+    pub fn rewind(&mut self, frames: usize) -> usize {
+        let current = match &mut self.current {
+            Some(current) => current,
+            None => return 0,
+        };
+
+        let mut rewound = 0;
+        while rewound < frames {
+            let delta = match self.deltas.pop_back() {
+                Some(delta) => delta,
+                None => break,
+            };
+
+            self.used -= delta.len();
+            apply(current, &delta);
+            rewound += 1;
+        }
+
+        rewound
+    }
+}
+
+// Encodes the runs where `to` differs from `from`. Runs separated by fewer
+// equal bytes than a run header are merged.
+fn diff(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+
+    let mut pos = 0;
+    while pos < from.len() {
+        if from[pos] == to[pos] {
+            pos += 1;
+            continue;
+        }
+
+        let start = pos;
+        let mut end = pos + 1;
+        let mut equal = 0;
+        while end + equal < from.len() && end - start + equal < usize::from(u16::MAX) && equal < RUN_HEADER_LEN {
+            if from[end + equal] == to[end + equal] {
+                equal += 1;
+            } else {
+                end += equal + 1;
+                equal = 0;
+            }
+        }
+
+        delta.extend_from_slice(&(start as u32).to_be_bytes());
+        delta.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        delta.extend_from_slice(&to[start..end]);
+        pos = end;
+    }
+
+    delta
+}
+
+fn apply(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    while pos < delta.len() {
+        let start = u32::from_be_bytes([delta[pos], delta[pos + 1], delta[pos + 2], delta[pos + 3]]) as usize;
+        let len = usize::from(u16::from_be_bytes([delta[pos + 4], delta[pos + 5]]));
+        pos += RUN_HEADER_LEN;
+
+        state[start..start + len].copy_from_slice(&delta[pos..pos + len]);
+        pos += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_apply() {
+        let old = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17];
+        let mut new = old.clone();
+        new[0] = 100;
+        new[3] = 103;
+        new[17] = 117;
+
+        let delta = diff(&new, &old);
+        // the first two changes share a run, the last is too far away
+        assert_eq!(delta.len(), RUN_HEADER_LEN + 4 + RUN_HEADER_LEN + 1);
+
+        apply(&mut new, &delta);
+        assert_eq!(new, old);
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut buffer = RewindBuffer::new(1 << 20);
+        for frame in 0..10u8 {
+            buffer.push(vec![frame, 0, 0, 0, frame * 2]);
+        }
+
+        assert_eq!(buffer.frames(), 9);
+        assert_eq!(buffer.rewind(3), 3);
+        assert_eq!(buffer.current(), Some(&[6, 0, 0, 0, 12][..]));
+
+        buffer.push(vec![42, 0, 0, 0, 0]);
+        assert_eq!(buffer.rewind(100), 7);
+        assert_eq!(buffer.current(), Some(&[0, 0, 0, 0, 0][..]));
+        assert_eq!(buffer.rewind(1), 0);
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut buffer = RewindBuffer::new(64);
+        for frame in 0..100u8 {
+            buffer.push(vec![frame; 16]);
+            assert!(buffer.memory_used() <= buffer.budget());
+        }
+
+        // each delta is a single 16 byte run
+        assert_eq!(buffer.frames(), (64 - 16) / (RUN_HEADER_LEN + 16));
+        assert_eq!(buffer.rewind(100), 2);
+        assert_eq!(buffer.current(), Some(&[97; 16][..]));
+
+        buffer.push(vec![0; 8]);
+        assert_eq!(buffer.frames(), 0);
+    }
+}
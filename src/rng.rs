@@ -0,0 +1,129 @@
+// Source of the bytes returned by Cxkk. The whole generator state fits in a
+// u64 so it can be stored in save states and restored exactly.
+pub trait Rng: Send {
+    fn next_byte(&mut self) -> u8;
+    fn state(&self) -> u64;
+    fn set_state(&mut self, state: u64);
+    fn clone_box(&self) -> Box<dyn Rng>;
+}
+
+impl Clone for Box<dyn Rng> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+// SplitMix64, small and good enough for games
+#[derive(Clone)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        SeededRng { state: seed }
+    }
+
+    pub fn from_entropy() -> Self {
+        SeededRng::new(rand::random())
+    }
+}
+
+impl Rng for SeededRng {
+    fn next_byte(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        (z >> 56) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+
+    fn clone_box(&self) -> Box<dyn Rng> {
+        Box::new(self.clone())
+    }
+}
+
+// Plays back a fixed sequence of bytes, starting over once it runs out
+#[derive(Clone)]
+pub struct ScriptedRng {
+    bytes: Vec<u8>,
+    pos: usize,
+}
+
+impl ScriptedRng {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        assert!(!bytes.is_empty());
+
+        ScriptedRng { bytes, pos: 0 }
+    }
+}
+
+impl Rng for ScriptedRng {
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.bytes[self.pos];
+        self.pos = (self.pos + 1) % self.bytes.len();
+        byte
+    }
+
+    fn state(&self) -> u64 {
+        self.pos as u64
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.pos = (state % self.bytes.len() as u64) as usize;
+    }
+
+    fn clone_box(&self) -> Box<dyn Rng> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_is_reproducible() {
+        let mut a = SeededRng::new(42);
+        let mut b = SeededRng::new(42);
+        let mut c = SeededRng::new(43);
+
+        let first: Vec<u8> = (0..32).map(|_| a.next_byte()).collect();
+        assert_eq!(first, (0..32).map(|_| b.next_byte()).collect::<Vec<u8>>());
+        assert_ne!(first, (0..32).map(|_| c.next_byte()).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut rng = SeededRng::new(7);
+        rng.next_byte();
+
+        let state = rng.state();
+        let expected: Vec<u8> = (0..8).map(|_| rng.next_byte()).collect();
+
+        rng.set_state(state);
+        assert_eq!((0..8).map(|_| rng.next_byte()).collect::<Vec<u8>>(), expected);
+    }
+
+    #[test]
+    fn test_scripted() {
+        let mut rng = ScriptedRng::new(vec![1, 2, 3]);
+
+        assert_eq!((0..5).map(|_| rng.next_byte()).collect::<Vec<u8>>(), vec![1, 2, 3, 1, 2]);
+        assert_eq!(rng.state(), 2);
+
+        rng.set_state(0);
+        assert_eq!(rng.next_byte(), 1);
+    }
+}
@@ -0,0 +1,272 @@
+use std::fmt;
+
+use crate::display::Display;
+
+// Same characters as HeadlessDisplay::to_ascii, indexed by pixel colour
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '%'];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScreenshotError {
+    BadPbm(&'static str),
+    BadAscii { line: usize, reason: &'static str },
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScreenshotError::BadPbm(reason) => write!(f, "invalid PBM image: {}", reason),
+            ScreenshotError::BadAscii { line, reason } => write!(f, "invalid ASCII screenshot on line {}: {}", line, reason),
+        }
+    }
+}
+
+impl std::error::Error for ScreenshotError {}
+
+// A copy of the screen at one point in time, one colour index per pixel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screenshot {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>, // row major
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScreenDiff {
+    Size { expected: (usize, usize), found: (usize, usize) },
+    // pixels that differ, and the smallest rectangle holding them as (x, y, width, height)
+    Pixels { count: usize, bounds: (usize, usize, usize, usize) },
+}
+
+impl fmt::Display for ScreenDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScreenDiff::Size { expected, found } => {
+                write!(f, "screen is {}x{}, expected {}x{}", found.0, found.1, expected.0, expected.1)
+            }
+            ScreenDiff::Pixels { count, bounds: (x, y, width, height) } => {
+                write!(f, "{} pixels differ in the {}x{} area at ({}, {})", count, width, height, x, y)
+            }
+        }
+    }
+}
+
+impl Screenshot {
+    pub fn capture(display: &dyn Display) -> Self {
+        let (width, height) = (display.width(), display.height());
+
+        let mut pixels = Vec::with_capacity(usize::from(width) * usize::from(height));
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(display.vram_get(x, y));
+            }
+        }
+
+        Screenshot { width: usize::from(width), height: usize::from(height), pixels }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    // Every lit pixel as colour 1, for comparing against a PBM file
+    pub fn monochrome(&self) -> Self {
+        Screenshot { pixels: self.pixels.iter().map(|&pixel| u8::from(pixel != 0)).collect(), ..*self }
+    }
+
+    pub fn to_ascii(&self) -> String {
+        let mut res = String::with_capacity((self.width + 1) * self.height);
+        for row in self.pixels.chunks(self.width) {
+            res.extend(row.iter().map(|&pixel| ASCII_PIXELS[usize::from(pixel)]));
+            res.push('\n');
+        }
+
+        res
+    }
+
+    // Plain (P1) PBM, which keeps golden files readable and diffable
+    pub fn to_pbm(&self) -> String {
+        let mut res = format!("P1\n{} {}\n", self.width, self.height);
+        for row in self.pixels.chunks(self.width) {
+            let bits: Vec<&str> = row.iter().map(|&pixel| if pixel != 0 { "1" } else { "0" }).collect();
+            res.push_str(&bits.join(" "));
+            res.push('\n');
+        }
+
+        res
+    }
+
+    pub fn from_ascii(text: &str) -> Result<Self, ScreenshotError> {
+        let mut width = None;
+        let mut pixels = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let row = line
+                .chars()
+                .map(|c| ASCII_PIXELS.iter().position(|&pixel| pixel == c).map(|pixel| pixel as u8))
+                .collect::<Option<Vec<u8>>>()
+                .ok_or(ScreenshotError::BadAscii { line: i + 1, reason: "unknown pixel character" })?;
+
+            if row.is_empty() || *width.get_or_insert(row.len()) != row.len() {
+                return Err(ScreenshotError::BadAscii { line: i + 1, reason: "line length differs from the first line" });
+            }
+            pixels.extend(row);
+        }
+
+        match width {
+            Some(width) => Ok(Screenshot { width, height: pixels.len() / width, pixels }),
+            None => Err(ScreenshotError::BadAscii { line: 1, reason: "empty screenshot" }),
+        }
+    }
+
+    pub fn from_pbm(text: &str) -> Result<Self, ScreenshotError> {
+        // comments run from '#' to the end of the line, everything else is whitespace separated
+        let mut tokens = text.lines().flat_map(|line| line.split('#').next().unwrap().split_whitespace());
+
+        if tokens.next() != Some("P1") {
+            return Err(ScreenshotError::BadPbm("only plain (P1) images are supported"));
+        }
+
+        let mut size = || tokens.next().and_then(|token| token.parse::<usize>().ok()).filter(|&size| size > 0);
+        let (width, height) = match (size(), size()) {
+            (Some(width), Some(height)) => (width, height),
+            _ => return Err(ScreenshotError::BadPbm("missing or invalid size")),
+        };
+
+        // P1 pixels don't need to be separated
+        let mut pixels = Vec::with_capacity(width * height);
+        for c in tokens.flat_map(str::chars) {
+            match c {
+                '0' | '1' => pixels.push(c as u8 - b'0'),
+                _ => return Err(ScreenshotError::BadPbm("pixels must be 0 or 1")),
+            }
+        }
+
+        if pixels.len() != width * height {
+            return Err(ScreenshotError::BadPbm("pixel count does not match the size"));
+        }
+
+        Ok(Screenshot { width, height, pixels })
+    }
+
+    // Compares against an expected screenshot, None if they are the same
+    pub fn diff(&self, expected: &Screenshot) -> Option<ScreenDiff> {
+        if (self.width, self.height) != (expected.width, expected.height) {
+            return Some(ScreenDiff::Size { expected: (expected.width, expected.height), found: (self.width, self.height) });
+        }
+
+        let mut count = 0;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (self.width, self.height, 0, 0);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.pixel(x, y) != expected.pixel(x, y) {
+                    count += 1;
+                    min_x = min_x.min(x);
+                    min_y = min_y.min(y);
+                    max_x = max_x.max(x);
+                    max_y = max_y.max(y);
+                }
+            }
+        }
+
+        if count == 0 {
+            return None;
+        }
+
+        Some(ScreenDiff::Pixels { count, bounds: (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1) })
+    }
+
+    // This screenshot as ASCII, with pixels that differ from `expected` shown as 'X'
+    pub fn diff_ascii(&self, expected: &Screenshot) -> String {
+        let mut res = self.to_ascii();
+        if (self.width, self.height) != (expected.width, expected.height) {
+            return res;
+        }
+
+        res = String::with_capacity(res.len());
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pixel = self.pixel(x, y);
+                res.push(if pixel != expected.pixel(x, y) { 'X' } else { ASCII_PIXELS[usize::from(pixel)] });
+            }
+            res.push('\n');
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::HeadlessDisplay;
+
+    fn sample() -> Screenshot {
+        let mut display = HeadlessDisplay::new();
+        display.vram_set(0, 0, 1);
+        display.vram_set(63, 31, 3);
+        display.vram_set(10, 5, 2);
+
+        Screenshot::capture(&display)
+    }
+
+    #[test]
+    fn test_ascii_round_trip() {
+        let screenshot = sample();
+
+        assert_eq!((screenshot.width(), screenshot.height()), (64, 32));
+        assert_eq!(Screenshot::from_ascii(&screenshot.to_ascii()), Ok(screenshot.clone()));
+
+        let mut display = HeadlessDisplay::new();
+        display.vram_set(63, 31, 3);
+        display.vram_set(10, 5, 2);
+        display.vram_set(0, 0, 1);
+        assert_eq!(screenshot.to_ascii(), display.to_ascii());
+
+        assert!(Screenshot::from_ascii("..#\n..\n").is_err());
+        assert!(Screenshot::from_ascii("..x\n").is_err());
+        assert!(Screenshot::from_ascii("").is_err());
+    }
+
+    #[test]
+    fn test_pbm_round_trip() {
+        let screenshot = sample();
+
+        let pbm = screenshot.to_pbm();
+        assert!(pbm.starts_with("P1\n64 32\n1 0 0"));
+        assert_eq!(Screenshot::from_pbm(&pbm), Ok(screenshot.monochrome()));
+
+        // comments and unseparated pixels are allowed
+        let small = Screenshot::from_pbm("P1\n# comment\n3 2\n101\n0 1 0\n").unwrap();
+        assert_eq!(small.to_ascii(), "#.#\n.#.\n");
+
+        assert!(Screenshot::from_pbm("P4\n3 2\n").is_err());
+        assert!(Screenshot::from_pbm("P1\n3 2\n1 0 1").is_err());
+        assert!(Screenshot::from_pbm("P1\n3 2\n1 0 2 0 0 0").is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let expected = sample();
+        assert_eq!(expected.diff(&expected), None);
+
+        let mut display = HeadlessDisplay::new();
+        display.vram_set(0, 0, 1);
+        display.vram_set(63, 31, 3);
+        display.vram_set(12, 7, 1);
+        let found = Screenshot::capture(&display);
+
+        assert_eq!(found.diff(&expected), Some(ScreenDiff::Pixels { count: 2, bounds: (10, 5, 3, 3) }));
+        assert_eq!(found.diff_ascii(&expected).lines().nth(5).unwrap().find('X'), Some(10));
+
+        display.set_hires(true);
+        assert!(matches!(Screenshot::capture(&display).diff(&expected), Some(ScreenDiff::Size { .. })));
+    }
+}
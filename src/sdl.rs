@@ -0,0 +1,7 @@
+mod audio;
+mod display;
+mod keypad;
+
+pub use self::audio::SdlAudio;
+pub use self::display::SdlDisplay;
+pub use self::keypad::{Hotkey, SdlKeypad};
@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+
+use emulator::audio::{Audio, Tone, ToneGenerator};
+
+struct Beeper {
+    generator: ToneGenerator,
+    muted: Arc<AtomicBool>,
+}
+
+impl AudioCallback for Beeper {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let muted = self.muted.load(Ordering::Relaxed);
+        for sample in out.iter_mut() {
+            let next = self.generator.next_sample();
+            *sample = if muted { 0.0 } else { next };
+        }
+    }
+}
+
+pub struct SdlAudio {
+    device: AudioDevice<Beeper>,
+    muted: Arc<AtomicBool>,
+    beeping: bool,
+}
+
+impl SdlAudio {
+    pub fn new(sdl_context: &sdl2::Sdl, tone: Tone, muted: bool) -> Result<Self, String> {
+        let audio_subsystem = sdl_context.audio()?;
+        let muted = Arc::new(AtomicBool::new(muted));
+
+        let desired = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let callback_muted = Arc::clone(&muted);
+        let device = audio_subsystem.open_playback(None, &desired, |spec| {
+            Beeper {
+                generator: ToneGenerator::new(tone, spec.freq as u32),
+                muted: callback_muted,
+            }
+        })?;
+
+        Ok(SdlAudio {
+            device,
+            muted,
+            beeping: false,
+        })
+    }
+
+    pub fn mute_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.muted)
+    }
+}
+
+impl Audio for SdlAudio {
+    fn set_beeping(&mut self, beeping: bool) {
+        if beeping == self.beeping {
+            return;
+        }
+
+        if beeping {
+            self.device.resume();
+        } else {
+            self.device.pause();
+        }
+
+        self.beeping = beeping;
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        self.device.lock().generator.set_pattern(pattern, pitch);
+    }
+}
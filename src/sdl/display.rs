@@ -0,0 +1,97 @@
+use sdl2::pixels::Color;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::rect::Rect;
+
+use emulator::display::{Display, MAX_HEIGHT, MAX_WIDTH};
+
+// indexed by pixel colour, i.e. which XO-CHIP planes are lit
+const PALETTE: [Color; 4] = [
+    Color { r: 0, g: 0, b: 0, a: 0xFF },
+    Color { r: 0, g: 255, b: 0, a: 0xFF },
+    Color { r: 255, g: 160, b: 0, a: 0xFF },
+    Color { r: 255, g: 255, b: 255, a: 0xFF },
+];
+
+pub struct SdlDisplay {
+    canvas: Canvas<Window>,
+    vram: [[u8; MAX_HEIGHT]; MAX_WIDTH], //access as vram[x][y]
+    hires: bool,
+    changed: bool,
+}
+
+impl SdlDisplay {
+    // With vsync, presenting waits for the monitor's next refresh
+    pub fn new(sdl_context: &sdl2::Sdl, scale: u8, vsync: bool) -> Self {
+        let vram = [[0; MAX_HEIGHT]; MAX_WIDTH];
+        let video_subsystem = sdl_context.video().unwrap();
+
+        let window = video_subsystem.window("Chip-8 Terminal Window", 64 * u32::from(scale), 32 * u32::from(scale))
+        .position_centered()
+        .build()
+        .unwrap();
+
+        let mut builder = window.into_canvas();
+        if vsync {
+            builder = builder.present_vsync();
+        }
+        let mut canvas = builder.build().unwrap();
+
+        // SDL scales the logical resolution up to the window size for us
+        canvas.set_logical_size(64, 32).unwrap();
+
+        SdlDisplay {
+            canvas,
+            vram,
+            hires: false,
+            changed: true,
+        }
+    }
+}
+
+impl Display for SdlDisplay {
+    fn redraw(&mut self) {
+        if !self.changed {
+            return;
+        }
+
+        self.canvas.set_draw_color(PALETTE[0]);
+        self.canvas.clear();
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let color = self.vram_get(x, y);
+                if color != 0 {
+                    self.canvas.set_draw_color(PALETTE[usize::from(color)]);
+                    self.canvas.fill_rect(Rect::new(i32::from(x), i32::from(y), 1, 1)).unwrap();
+                }
+            }
+        }
+
+        self.canvas.present();
+        self.changed = false;
+    }
+
+    fn clear(&mut self) {
+        self.vram = [[0; MAX_HEIGHT]; MAX_WIDTH];
+        self.changed = true;
+    }
+
+    fn hires(&self) -> bool {
+        self.hires
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.canvas.set_logical_size(u32::from(self.width()), u32::from(self.height())).unwrap();
+        self.clear();
+    }
+
+    fn vram_get(&self, x: u8, y: u8) -> u8 {
+        self.vram[usize::from(x)][usize::from(y)]
+    }
+
+    fn vram_set(&mut self, x: u8, y: u8, color: u8) {
+        self.vram[usize::from(x)][usize::from(y)] = color;
+        self.changed = true;
+    }
+}
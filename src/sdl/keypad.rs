@@ -0,0 +1,236 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use sdl2::controller::{Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::{KeyboardState, Mod, Scancode};
+use sdl2::GameControllerSubsystem;
+
+use emulator::keymap::Keymap;
+use emulator::keypad::{Keypad, KeypadState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    Break,
+    ToggleMute,
+    Pause,
+    SaveState(u8),
+    LoadState(u8),
+    // sent when the rewind key goes down and again when it comes up
+    Rewind(bool),
+    // likewise for the fast forward key
+    Turbo(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HostKey {
+    Key(Scancode),
+    // a button on one player's controller, or on any controller if player is None
+    Button { player: Option<usize>, button: Button },
+}
+
+impl HostKey {
+    // Keyboard keys are SDL scancode names, written as Comma for the key that
+    // separates names in a keymap file. Controller buttons are "Pad <BUTTON>"
+    // for any controller or "Pad<N> <BUTTON>" for player N's, with SDL's
+    // button names (a, b, x, y, start, dpup, dpleft, ...).
+    fn from_name(name: &str) -> Option<HostKey> {
+        if let Some((pad, button)) = name.split_once(' ') {
+            if pad.len() >= 3 && pad[..3].eq_ignore_ascii_case("pad") {
+                let player = match &pad[3..] {
+                    "" => None,
+                    number => Some(number.parse::<usize>().ok().filter(|&number| number > 0)? - 1),
+                };
+                return Some(HostKey::Button { player, button: Button::from_string(&button.to_ascii_lowercase())? });
+            }
+        }
+
+        if name.eq_ignore_ascii_case("comma") {
+            return Some(HostKey::Key(Scancode::Comma));
+        }
+
+        Scancode::from_name(name).map(HostKey::Key)
+    }
+
+    // Whether pressing one could also press the other
+    fn overlaps(self, other: HostKey) -> bool {
+        match (self, other) {
+            (HostKey::Button { player, button }, HostKey::Button { player: other_player, button: other_button }) => {
+                button == other_button && (player.is_none() || other_player.is_none() || player == other_player)
+            }
+            _ => self == other,
+        }
+    }
+}
+
+pub struct SdlKeypad {
+    events: sdl2::EventPump,
+    quit: bool,
+    hotkeys: Rc<RefCell<VecDeque<Hotkey>>>,
+    // host keys for each CHIP-8 key
+    keys: [Vec<HostKey>; 16],
+    controller_subsystem: GameControllerSubsystem,
+    // indexed by player, a disconnected player's slot is reused by the next
+    // controller plugged in
+    controllers: Vec<Option<GameController>>,
+}
+
+impl SdlKeypad {
+    pub fn new(sdl_context: &sdl2::Sdl, keymap: &Keymap) -> Result<Self, String> {
+        let mut keys: [Vec<HostKey>; 16] = Default::default();
+        let mut names: Vec<(&str, HostKey)> = Vec::new();
+        for (key, host_keys) in keys.iter_mut().enumerate() {
+            for name in keymap.host_keys(key as u8) {
+                let host_key = HostKey::from_name(name).ok_or_else(|| format!("unknown host key '{}' in keymap", name))?;
+                if let HostKey::Key(code) = host_key {
+                    if SdlKeypad::scancode_to_hotkey(code, Mod::empty()).is_some() {
+                        return Err(format!("host key '{}' is already a hotkey", name));
+                    }
+                }
+                if let Some((other, _)) = names.iter().find(|&&(_, other)| other.overlaps(host_key)) {
+                    return Err(format!("host keys '{}' and '{}' overlap", other, name));
+                }
+
+                names.push((name, host_key));
+                host_keys.push(host_key);
+            }
+        }
+
+        // controllers that are already plugged in show up as added events on
+        // the first poll, just like ones plugged in later
+        Ok(SdlKeypad {
+            events: sdl_context.event_pump()?,
+            quit: false,
+            hotkeys: Rc::new(RefCell::new(VecDeque::new())),
+            keys,
+            controller_subsystem: sdl_context.game_controller()?,
+            controllers: Vec::new(),
+        })
+    }
+
+    // Front end hotkeys are collected while polling for events, since the
+    // event pump is owned by the keypad. The returned queue is drained by main.
+    pub fn hotkeys(&self) -> Rc<RefCell<VecDeque<Hotkey>>> {
+        Rc::clone(&self.hotkeys)
+    }
+
+    fn scancode_to_hotkey(code: Scancode, keymod: Mod) -> Option<Hotkey> {
+        let slot = match code {
+            Scancode::F12 => return Some(Hotkey::Break),
+            Scancode::M => return Some(Hotkey::ToggleMute),
+            Scancode::P => return Some(Hotkey::Pause),
+            Scancode::Tab => return Some(Hotkey::Turbo(true)),
+            Scancode::Backspace => return Some(Hotkey::Rewind(true)),
+            Scancode::F1 => 1,
+            Scancode::F2 => 2,
+            Scancode::F3 => 3,
+            Scancode::F4 => 4,
+            _ => return None,
+        };
+
+        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+            Some(Hotkey::SaveState(slot))
+        } else {
+            Some(Hotkey::LoadState(slot))
+        }
+    }
+
+    fn connect_controller(&mut self, joystick_index: u32) {
+        let controller = match self.controller_subsystem.open(joystick_index) {
+            Ok(controller) => controller,
+            Err(err) => {
+                eprintln!("warning: failed to open controller {}: {}", joystick_index, err);
+                return;
+            }
+        };
+
+        let player = self.controllers.iter().position(Option::is_none).unwrap_or(self.controllers.len());
+        println!("Controller '{}' connected as player {}", controller.name(), player + 1);
+        if player == self.controllers.len() {
+            self.controllers.push(Some(controller));
+        } else {
+            self.controllers[player] = Some(controller);
+        }
+    }
+
+    fn disconnect_controller(&mut self, instance_id: i32) {
+        if let Some(player) = self.player(instance_id) {
+            println!("Controller for player {} disconnected", player + 1);
+            self.controllers[player] = None;
+        }
+    }
+
+    fn player(&self, instance_id: i32) -> Option<usize> {
+        self.controllers
+            .iter()
+            .position(|controller| controller.as_ref().is_some_and(|controller| controller.instance_id() == instance_id))
+    }
+
+    fn is_down(&self, host_key: HostKey, keyboard: &KeyboardState) -> bool {
+        match host_key {
+            HostKey::Key(code) => keyboard.is_scancode_pressed(code),
+            HostKey::Button { player: Some(player), button } => {
+                self.controllers.get(player).and_then(Option::as_ref).is_some_and(|controller| controller.button(button))
+            }
+            HostKey::Button { player: None, button } => self.controllers.iter().flatten().any(|controller| controller.button(button)),
+        }
+    }
+
+    // Marks the keys bound to a host key that just went down
+    fn press(&self, keys: &mut KeypadState, pressed: HostKey) {
+        for (key, host_keys) in self.keys.iter().enumerate() {
+            if host_keys.iter().any(|&host_key| host_key.overlaps(pressed)) {
+                keys.set(key as u8, true);
+            }
+        }
+    }
+}
+
+impl Keypad for SdlKeypad {
+    // A key counts as pressed if any of its host keys is down now, or went
+    // down since the last poll, so a quick tap still lasts one frame
+    fn poll(&mut self) -> KeypadState {
+        let mut keys = KeypadState::default();
+
+        let events: Vec<Event> = self.events.poll_iter().collect();
+        for event in events {
+            match event {
+                Event::Quit{ .. } => self.quit = true,
+                Event::KeyDown{ scancode: Some(code), keymod, repeat: false, .. } => {
+                    if let Some(hotkey) = SdlKeypad::scancode_to_hotkey(code, keymod) {
+                        self.hotkeys.borrow_mut().push_back(hotkey);
+                    }
+                    self.press(&mut keys, HostKey::Key(code));
+                }
+                Event::KeyUp{ scancode: Some(Scancode::Backspace), .. } => {
+                    self.hotkeys.borrow_mut().push_back(Hotkey::Rewind(false));
+                }
+                Event::KeyUp{ scancode: Some(Scancode::Tab), .. } => {
+                    self.hotkeys.borrow_mut().push_back(Hotkey::Turbo(false));
+                }
+                Event::ControllerDeviceAdded{ which, .. } => self.connect_controller(which),
+                Event::ControllerDeviceRemoved{ which, .. } => self.disconnect_controller(which),
+                Event::ControllerButtonDown{ which, button, .. } => {
+                    if let Some(player) = self.player(which) {
+                        self.press(&mut keys, HostKey::Button { player: Some(player), button });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let keyboard = self.events.keyboard_state();
+        for (key, host_keys) in self.keys.iter().enumerate() {
+            if host_keys.iter().any(|&host_key| self.is_down(host_key, &keyboard)) {
+                keys.set(key as u8, true);
+            }
+        }
+
+        keys
+    }
+
+    fn check_for_exit(&mut self) -> bool {
+        self.quit
+    }
+}
@@ -0,0 +1,363 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::cpu::KeyWait;
+use crate::display::{MAX_HEIGHT, MAX_WIDTH, PLANE_COUNT};
+use crate::ram::{RAM_SIZE, XO_CHIP_RAM_SIZE};
+
+const MAGIC: &[u8; 4] = b"CH8S";
+pub const VERSION: u16 = 6;
+
+#[derive(Debug)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion { found: u16, expected: u16 },
+    Truncated,
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state file"),
+            StateError::UnsupportedVersion { found, expected } => {
+                write!(f, "save state version {} is not supported (expected version {})", found, expected)
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Corrupt(reason) => write!(f, "save state is corrupt: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub regs: [u8; 16],
+    pub pc: u16,
+    pub stack: [u16; 16],
+    pub sp: u8,
+    pub i: u16,
+    pub dt: u8,
+    pub st: u8,
+    pub ram: Vec<u8>,
+    pub rpl: [u8; 16],
+    pub hires: bool,
+    pub planes: u8,
+    pub pattern: Option<[u8; 16]>,
+    pub pitch: u8,
+    pub frame_cycle: u32,
+    pub cycles: u64,
+    pub frames: u64,
+    pub drawn_this_frame: bool,
+    pub key_wait: Option<KeyWait>,
+    pub rng_state: u64,
+    pub vram: [[u8; MAX_HEIGHT]; MAX_WIDTH], //access as vram[x][y]
+}
+
+impl fmt::Debug for SaveState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SaveState")
+            .field("regs", &self.regs)
+            .field("pc", &self.pc)
+            .field("stack", &self.stack)
+            .field("sp", &self.sp)
+            .field("i", &self.i)
+            .field("dt", &self.dt)
+            .field("st", &self.st)
+            .field("rpl", &self.rpl)
+            .field("hires", &self.hires)
+            .field("planes", &self.planes)
+            .field("pattern", &self.pattern)
+            .field("pitch", &self.pitch)
+            .field("frame_cycle", &self.frame_cycle)
+            .field("cycles", &self.cycles)
+            .field("frames", &self.frames)
+            .field("drawn_this_frame", &self.drawn_this_frame)
+            .field("key_wait", &self.key_wait)
+            .field("rng_state", &self.rng_state)
+            .finish()
+    }
+}
+
+// shared with the movie file parser
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < len {
+            return Err(StateError::Truncated);
+        }
+
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
+impl SaveState {
+    // Layout (all integers big endian):
+    //   magic "CH8S", version u16,
+    //   regs [u8; 16], pc u16, stack [u16; 16], sp u8, i u16, dt u8, st u8,
+    //   rpl [u8; 16], hires u8, planes u8,
+    //   has pattern u8, pattern [u8; 16], pitch u8,
+    //   frame cycle u32, cycles u64, frames u64, drawn this frame u8,
+    //   key wait u8 (0 none, 1 waiting for a press, 2 waiting for a release),
+    //   ignored keys u16, pressed key u8, rng state u64,
+    //   ram length u32, ram bytes,
+    //   one 128x64 bitmap per plane, packed 8 pixels per byte, row major,
+    //   most significant bit leftmost
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(128 + self.ram.len() + usize::from(PLANE_COUNT) * MAX_WIDTH * MAX_HEIGHT / 8);
+
+        res.extend_from_slice(MAGIC);
+        res.extend_from_slice(&VERSION.to_be_bytes());
+
+        res.extend_from_slice(&self.regs);
+        res.extend_from_slice(&self.pc.to_be_bytes());
+        for addr in self.stack.iter() {
+            res.extend_from_slice(&addr.to_be_bytes());
+        }
+        res.push(self.sp);
+        res.extend_from_slice(&self.i.to_be_bytes());
+        res.push(self.dt);
+        res.push(self.st);
+        res.extend_from_slice(&self.rpl);
+        res.push(u8::from(self.hires));
+        res.push(self.planes);
+        res.push(u8::from(self.pattern.is_some()));
+        res.extend_from_slice(&self.pattern.unwrap_or([0; 16]));
+        res.push(self.pitch);
+        res.extend_from_slice(&self.frame_cycle.to_be_bytes());
+        res.extend_from_slice(&self.cycles.to_be_bytes());
+        res.extend_from_slice(&self.frames.to_be_bytes());
+        res.push(u8::from(self.drawn_this_frame));
+        let (waiting, ignored, pressed) = match self.key_wait {
+            None => (0, 0, 0),
+            Some(KeyWait { ignored, pressed: None }) => (1, ignored, 0),
+            Some(KeyWait { ignored, pressed: Some(key) }) => (2, ignored, key),
+        };
+        res.push(waiting);
+        res.extend_from_slice(&ignored.to_be_bytes());
+        res.push(pressed);
+        res.extend_from_slice(&self.rng_state.to_be_bytes());
+
+        res.extend_from_slice(&u32::try_from(self.ram.len()).unwrap().to_be_bytes());
+        res.extend_from_slice(&self.ram);
+
+        for plane in 0..PLANE_COUNT {
+            let mut byte = 0u8;
+            for pixel in 0..MAX_WIDTH * MAX_HEIGHT {
+                if self.vram[pixel % MAX_WIDTH][pixel / MAX_WIDTH] & (1 << plane) != 0 {
+                    byte |= 0x80 >> (pixel % 8);
+                }
+                if pixel % 8 == 7 {
+                    res.push(byte);
+                    byte = 0;
+                }
+            }
+        }
+
+        res
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<SaveState, StateError> {
+        let mut reader = Reader { bytes };
+
+        if reader.take(4).map_err(|_| StateError::BadMagic)? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion { found: version, expected: VERSION });
+        }
+
+        let mut regs = [0; 16];
+        regs.copy_from_slice(reader.take(16)?);
+        let pc = reader.u16()?;
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = reader.u16()?;
+        }
+        let sp = reader.u8()?;
+        let i = reader.u16()?;
+        let dt = reader.u8()?;
+        let st = reader.u8()?;
+        let mut rpl = [0; 16];
+        rpl.copy_from_slice(reader.take(16)?);
+        let hires = match reader.u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(StateError::Corrupt("invalid display mode")),
+        };
+        let planes = reader.u8()?;
+        if planes >= 1 << PLANE_COUNT {
+            return Err(StateError::Corrupt("invalid plane selection"));
+        }
+        let has_pattern = reader.u8()?;
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(reader.take(16)?);
+        let pattern = match has_pattern {
+            0 => None,
+            1 => Some(pattern),
+            _ => return Err(StateError::Corrupt("invalid audio pattern flag")),
+        };
+        let pitch = reader.u8()?;
+        let frame_cycle = reader.u32()?;
+        let cycles = reader.u64()?;
+        let frames = reader.u64()?;
+        let drawn_this_frame = match reader.u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(StateError::Corrupt("invalid frame state")),
+        };
+        let waiting = reader.u8()?;
+        let ignored = reader.u16()?;
+        let pressed = reader.u8()?;
+        let key_wait = match waiting {
+            0 => None,
+            1 => Some(KeyWait { ignored, pressed: None }),
+            2 if pressed < 16 => Some(KeyWait { ignored, pressed: Some(pressed) }),
+            _ => return Err(StateError::Corrupt("invalid key wait")),
+        };
+        let rng_state = reader.u64()?;
+
+        let ram_len = reader.u32()? as usize;
+        if ram_len != RAM_SIZE && ram_len != XO_CHIP_RAM_SIZE {
+            return Err(StateError::Corrupt("unexpected memory size"));
+        }
+        let ram = reader.take(ram_len)?.to_vec();
+
+        let mut vram = [[0; MAX_HEIGHT]; MAX_WIDTH];
+        for plane in 0..PLANE_COUNT {
+            let packed = reader.take(MAX_WIDTH * MAX_HEIGHT / 8)?;
+            for pixel in 0..MAX_WIDTH * MAX_HEIGHT {
+                if packed[pixel / 8] & (0x80 >> (pixel % 8)) != 0 {
+                    vram[pixel % MAX_WIDTH][pixel / MAX_WIDTH] |= 1 << plane;
+                }
+            }
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(StateError::Corrupt("trailing data"));
+        }
+        if usize::from(sp) > stack.len() {
+            return Err(StateError::Corrupt("stack pointer out of range"));
+        }
+        if usize::from(pc) >= ram.len() {
+            return Err(StateError::Corrupt("program counter out of range"));
+        }
+
+        Ok(SaveState { regs, pc, stack, sp, i, dt, st, rpl, hires, planes, pattern, pitch, frame_cycle, cycles, frames, drawn_this_frame, key_wait, rng_state, ram, vram })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_state() -> SaveState {
+        let mut ram = vec![0; 0x1000];
+        ram[0x200] = 0x12;
+        ram[0xFFF] = 0x34;
+
+        let mut vram = [[0; MAX_HEIGHT]; MAX_WIDTH];
+        vram[0][0] = 1;
+        vram[127][63] = 3;
+        vram[9][17] = 2;
+
+        SaveState {
+            regs: [7; 16],
+            pc: 0x246,
+            stack: [0x202; 16],
+            sp: 3,
+            i: 0x300,
+            dt: 10,
+            st: 20,
+            rpl: [5; 16],
+            hires: true,
+            planes: 3,
+            pattern: Some([0xAA; 16]),
+            pitch: 80,
+            frame_cycle: 3,
+            cycles: 1_000_003,
+            frames: 125_000,
+            drawn_this_frame: true,
+            key_wait: Some(KeyWait { ignored: 0x8001, pressed: Some(0x3) }),
+            rng_state: 0xDEAD_BEEF,
+            ram,
+            vram,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let state = sample_state();
+
+        assert_eq!(SaveState::from_bytes(&state.to_bytes()).unwrap(), state);
+
+        let mut state = sample_state();
+        state.ram = vec![0x55; 0x10000];
+        state.pattern = None;
+        state.key_wait = Some(KeyWait { ignored: 0, pressed: None });
+        assert_eq!(SaveState::from_bytes(&state.to_bytes()).unwrap(), state);
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        let mut bytes = sample_state().to_bytes();
+        bytes[0] = b'X';
+
+        assert!(matches!(SaveState::from_bytes(&bytes), Err(StateError::BadMagic)));
+        assert!(matches!(SaveState::from_bytes(b"CH"), Err(StateError::BadMagic)));
+    }
+
+    #[test]
+    fn test_old_version_rejected() {
+        let mut bytes = sample_state().to_bytes();
+        bytes[4..6].copy_from_slice(&0u16.to_be_bytes());
+
+        match SaveState::from_bytes(&bytes) {
+            Err(StateError::UnsupportedVersion { found: 0, expected: VERSION }) => {}
+            other => panic!("expected version error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_truncated_and_corrupt() {
+        let bytes = sample_state().to_bytes();
+
+        assert!(matches!(SaveState::from_bytes(&bytes[..bytes.len() - 1]), Err(StateError::Truncated)));
+
+        let mut state = sample_state();
+        state.sp = 17;
+        assert!(matches!(SaveState::from_bytes(&state.to_bytes()), Err(StateError::Corrupt(_))));
+
+        let mut bytes = sample_state().to_bytes();
+        bytes[77] = 2;
+        assert!(matches!(SaveState::from_bytes(&bytes), Err(StateError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,365 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::audio::Audio;
+use crate::cpu::{Cpu, StepOutcome};
+use crate::display::Display;
+use crate::error::EmuError;
+use crate::instruction::{decode, Instruction};
+use crate::keypad::Keypad;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    JsonLines,
+    Csv,
+}
+
+impl FromStr for TraceFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" | "jsonl" => Ok(TraceFormat::JsonLines),
+            "csv" => Ok(TraceFormat::Csv),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpcodeClass {
+    // jumps, calls, returns and register compare skips
+    Flow,
+    // register arithmetic and logic, including RND
+    Alu,
+    // anything that reads or writes I or memory
+    Memory,
+    Draw,
+    // key skips and waits
+    Input,
+    Timer,
+    Sound,
+}
+
+impl OpcodeClass {
+    pub fn of(instruction: Instruction) -> Self {
+        match instruction {
+            Instruction::Ret
+            | Instruction::Exit
+            | Instruction::JpAddr(_)
+            | Instruction::CallAddr(_)
+            | Instruction::JpV0Addr(_)
+            | Instruction::SeVxByte(..)
+            | Instruction::SneVxByte(..)
+            | Instruction::SeVxVy(..)
+            | Instruction::SneVxVy(..) => OpcodeClass::Flow,
+            Instruction::LdVxByte(..)
+            | Instruction::AddVxByte(..)
+            | Instruction::LdVxVy(..)
+            | Instruction::OrVxVy(..)
+            | Instruction::AndVxVy(..)
+            | Instruction::XorVxVy(..)
+            | Instruction::AddVxVy(..)
+            | Instruction::SubVxVy(..)
+            | Instruction::ShrVxVy(..)
+            | Instruction::SubnVxVy(..)
+            | Instruction::ShlVxVy(..)
+            | Instruction::RndVxByte(..) => OpcodeClass::Alu,
+            Instruction::LdIAddr(_)
+            | Instruction::LdILong
+            | Instruction::AddIVx(_)
+            | Instruction::LdFVx(_)
+            | Instruction::LdHfVx(_)
+            | Instruction::LdBVx(_)
+            | Instruction::LdIVx(_)
+            | Instruction::LdVxI(_)
+            | Instruction::LdRVx(_)
+            | Instruction::LdVxR(_)
+            | Instruction::SaveVxVy(..)
+            | Instruction::LoadVxVy(..) => OpcodeClass::Memory,
+            Instruction::Cls
+            | Instruction::ScdNibble(_)
+            | Instruction::Scr
+            | Instruction::Scl
+            | Instruction::Low
+            | Instruction::High
+            | Instruction::PlaneN(_)
+            | Instruction::DrwVxVyNibble(..) => OpcodeClass::Draw,
+            Instruction::SkpVx(_) | Instruction::SknpVx(_) | Instruction::LdVxK(_) => OpcodeClass::Input,
+            Instruction::LdVxDt(_) | Instruction::LdDtVx(_) => OpcodeClass::Timer,
+            Instruction::LdStVx(_) | Instruction::LdAudioI | Instruction::LdPitchVx(_) => OpcodeClass::Sound,
+        }
+    }
+}
+
+impl FromStr for OpcodeClass {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "flow" => Ok(OpcodeClass::Flow),
+            "alu" => Ok(OpcodeClass::Alu),
+            "memory" => Ok(OpcodeClass::Memory),
+            "draw" => Ok(OpcodeClass::Draw),
+            "input" => Ok(OpcodeClass::Input),
+            "timer" => Ok(OpcodeClass::Timer),
+            "sound" => Ok(OpcodeClass::Sound),
+            _ => Err(()),
+        }
+    }
+}
+
+// Which executed instructions make it into the trace. An empty class list
+// lets every class through.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    pub pc_range: Option<RangeInclusive<u16>>,
+    pub classes: Vec<OpcodeClass>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, instruction: Instruction) -> bool {
+        self.pc_range.as_ref().map_or(true, |range| range.contains(&pc))
+            && (self.classes.is_empty() || self.classes.contains(&OpcodeClass::of(instruction)))
+    }
+}
+
+// A register that an instruction changed, and its new value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    V(u8, u8),
+    I(u16),
+    Sp(u8),
+    Dt(u8),
+    St(u8),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Change::V(reg, val) => write!(f, "V{:01x}=0x{:02x}", reg, val),
+            Change::I(val) => write!(f, "I=0x{:04x}", val),
+            Change::Sp(val) => write!(f, "SP=0x{:02x}", val),
+            Change::Dt(val) => write!(f, "DT=0x{:02x}", val),
+            Change::St(val) => write!(f, "ST=0x{:02x}", val),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Registers {
+    regs: [u8; 16],
+    i: u16,
+    sp: u8,
+    dt: u8,
+    st: u8,
+}
+
+impl Registers {
+    fn of<D: Display, K: Keypad, A: Audio>(cpu: &Cpu<D, K, A>) -> Self {
+        Registers { regs: *cpu.regs(), i: cpu.i(), sp: cpu.sp(), dt: cpu.dt(), st: cpu.st() }
+    }
+
+    fn changes(&self, after: &Registers) -> Vec<Change> {
+        let mut res: Vec<Change> = (0..16u8)
+            .filter(|&reg| self.regs[usize::from(reg)] != after.regs[usize::from(reg)])
+            .map(|reg| Change::V(reg, after.regs[usize::from(reg)]))
+            .collect();
+
+        if self.i != after.i {
+            res.push(Change::I(after.i));
+        }
+        if self.sp != after.sp {
+            res.push(Change::Sp(after.sp));
+        }
+        if self.dt != after.dt {
+            res.push(Change::Dt(after.dt));
+        }
+        if self.st != after.st {
+            res.push(Change::St(after.st));
+        }
+
+        res
+    }
+}
+
+// Writes one record per executed instruction: the cycle it ran on, its
+// address, opcode and disassembly, and the registers it changed. Timers
+// show up as changes on the instruction that ends a frame.
+//
+// JSON lines:
+//     {"cycle":3,"pc":"0x0204","opcode":"0x7005","instruction":"ADD V0, 0x05","changes":{"V0":"0x05"}}
+// CSV, with a header line:
+//     3,0x0204,0x7005,"ADD V0, 0x05",V0=0x05
+//
+// Write errors stop the trace and are reported by finish, so a full disk
+// doesn't stop the game.
+pub struct Tracer<W: Write> {
+    out: W,
+    format: TraceFormat,
+    filter: TraceFilter,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(mut out: W, format: TraceFormat, filter: TraceFilter) -> Self {
+        let mut error = None;
+        if format == TraceFormat::Csv {
+            error = writeln!(out, "cycle,pc,opcode,instruction,changes").err();
+        }
+
+        Tracer { out, format, filter, error }
+    }
+
+    // Runs one instruction like Cpu::tick, tracing it if it passes the filter
+    pub fn step<D: Display, K: Keypad, A: Audio>(&mut self, cpu: &mut Cpu<D, K, A>) -> Result<StepOutcome, EmuError> {
+        let (pc, cycle) = (cpu.pc(), cpu.cycles());
+        // there's no opcode to read past the end of memory, tick reports the error
+        if usize::from(pc) + 2 > cpu.memory_size() {
+            return cpu.tick();
+        }
+        let opcode = cpu.opcode_at(pc);
+        let before = Registers::of(cpu);
+
+        let outcome = cpu.tick()?;
+
+        // an Fx0A wait runs again every tick until it ends, only its last run is traced
+        if outcome == StepOutcome::WaitingForKey || self.error.is_some() {
+            return Ok(outcome);
+        }
+        let instruction = decode(opcode).expect("the Cpu only executes valid opcodes");
+        if !self.filter.matches(pc, instruction) {
+            return Ok(outcome);
+        }
+
+        let changes = before.changes(&Registers::of(cpu));
+        self.error = self.write_record(cycle, pc, opcode, instruction, &changes).err();
+
+        Ok(outcome)
+    }
+
+    fn write_record(&mut self, cycle: u64, pc: u16, opcode: u16, instruction: Instruction, changes: &[Change]) -> io::Result<()> {
+        match self.format {
+            TraceFormat::JsonLines => {
+                // disassembly never contains quotes or backslashes, so nothing needs escaping
+                let changes: Vec<String> = changes
+                    .iter()
+                    .map(|change| {
+                        let text = change.to_string();
+                        let (reg, val) = text.split_once('=').unwrap();
+                        format!("\"{}\":\"{}\"", reg, val)
+                    })
+                    .collect();
+                writeln!(
+                    self.out,
+                    "{{\"cycle\":{},\"pc\":\"0x{:04x}\",\"opcode\":\"0x{:04x}\",\"instruction\":\"{}\",\"changes\":{{{}}}}}",
+                    cycle,
+                    pc,
+                    opcode,
+                    instruction,
+                    changes.join(",")
+                )
+            }
+            TraceFormat::Csv => {
+                let changes: Vec<String> = changes.iter().map(Change::to_string).collect();
+                writeln!(self.out, "{},0x{:04x},0x{:04x},\"{}\",{}", cycle, pc, opcode, instruction, changes.join(" "))
+            }
+        }
+    }
+
+    // Flushes the trace, returning the first write error if there was one
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.out.flush()?;
+
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cpu::harness::exec;
+    use crate::quirks::QuirkProfile;
+
+    fn trace(program: &[u16], ticks: usize, format: TraceFormat, filter: TraceFilter) -> Vec<String> {
+        let mut cpu = exec(program, QuirkProfile::Vip, 0, |_| {});
+        let mut tracer = Tracer::new(Vec::new(), format, filter);
+        for _ in 0..ticks {
+            tracer.step(&mut cpu).unwrap();
+        }
+
+        String::from_utf8(tracer.finish().unwrap()).unwrap().lines().map(String::from).collect()
+    }
+
+    // LD V0, 0x05; ADD V0, 0x01; LD I, 0x300; JP 0x200
+    const PROGRAM: [u16; 4] = [0x6005, 0x7001, 0xA300, 0x1200];
+
+    #[test]
+    fn test_json_lines() {
+        let lines = trace(&PROGRAM, 4, TraceFormat::JsonLines, TraceFilter::default());
+
+        assert_eq!(
+            lines,
+            [
+                r#"{"cycle":0,"pc":"0x0200","opcode":"0x6005","instruction":"LD V0, 0x05","changes":{"V0":"0x05"}}"#,
+                r#"{"cycle":1,"pc":"0x0202","opcode":"0x7001","instruction":"ADD V0, 0x01","changes":{"V0":"0x06"}}"#,
+                r#"{"cycle":2,"pc":"0x0204","opcode":"0xa300","instruction":"LD I, 0x0300","changes":{"I":"0x0300"}}"#,
+                r#"{"cycle":3,"pc":"0x0206","opcode":"0x1200","instruction":"JP 0x0200","changes":{}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        // LD V0, 0x03; LD DT, V0; LD V1, 0x00
+        let lines = trace(&[0x6003, 0xF015, 0x6100], 3, TraceFormat::Csv, TraceFilter::default());
+
+        assert_eq!(lines[0], "cycle,pc,opcode,instruction,changes");
+        assert_eq!(lines[1], "0,0x0200,0x6003,\"LD V0, 0x03\",V0=0x03");
+        assert_eq!(lines[2], "1,0x0202,0xf015,\"LD DT, V0\",DT=0x03");
+        assert_eq!(lines[3], "2,0x0204,0x6100,\"LD V1, 0x00\",");
+    }
+
+    #[test]
+    fn test_filters() {
+        let by_pc = TraceFilter { pc_range: Some(0x202..=0x204), classes: Vec::new() };
+        let lines = trace(&PROGRAM, 8, TraceFormat::Csv, by_pc);
+        let pcs: Vec<&str> = lines[1..].iter().map(|line| &line[2..8]).collect();
+        assert_eq!(pcs, ["0x0202", "0x0204", "0x0202", "0x0204"]);
+
+        let by_class = TraceFilter { pc_range: None, classes: vec![OpcodeClass::Flow, OpcodeClass::Memory] };
+        let lines = trace(&PROGRAM, 4, TraceFormat::Csv, by_class);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains("LD I, 0x0300"));
+        assert!(lines[2].contains("JP 0x0200"));
+    }
+
+    #[test]
+    fn test_pc_past_the_end() {
+        // JP 0xFFF, leaving only one byte of opcode before the end of memory
+        let mut cpu = exec(&[0x1FFF], QuirkProfile::Vip, 0, |_| {});
+        let mut tracer = Tracer::new(Vec::new(), TraceFormat::Csv, TraceFilter::default());
+
+        assert!(tracer.step(&mut cpu).is_ok());
+        assert_eq!(tracer.step(&mut cpu), Err(EmuError::MemoryOutOfBounds { pc: 0xFFF, addr: 0x1000 }));
+        // the header and the jump, nothing for the instruction that never ran
+        assert_eq!(String::from_utf8(tracer.finish().unwrap()).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_opcode_classes() {
+        assert_eq!(OpcodeClass::of(Instruction::SneVxVy(1, 2)), OpcodeClass::Flow);
+        assert_eq!(OpcodeClass::of(Instruction::RndVxByte(0, 0xFF)), OpcodeClass::Alu);
+        assert_eq!(OpcodeClass::of(Instruction::LdFVx(3)), OpcodeClass::Memory);
+        assert_eq!(OpcodeClass::of(Instruction::LdVxK(3)), OpcodeClass::Input);
+        assert_eq!("Draw".parse(), Ok(OpcodeClass::Draw));
+        assert_eq!("csv".parse(), Ok(TraceFormat::Csv));
+        assert_eq!("xml".parse::<TraceFormat>(), Err(()));
+    }
+}
@@ -0,0 +1,27 @@
+use emulator::chip8::Chip8;
+use emulator::cpu::StepOutcome;
+use emulator::quirks::QuirkProfile;
+
+// Runs a bundled ROM headless for `frames` emulated frames and returns the screen
+fn run_rom(name: &str, profile: QuirkProfile, frames: usize) -> String {
+    let rom = std::fs::read(format!("roms/{}", name)).unwrap();
+
+    let mut chip8 = Chip8::new(profile);
+    chip8.load_rom(&rom).unwrap();
+    for _ in 0..frames {
+        assert!(matches!(chip8.run_frame(), Ok(StepOutcome::Executed(_))));
+    }
+
+    chip8.framebuffer().to_ascii()
+}
+
+fn assert_matches_golden(screen: &str, golden: &str) {
+    let expected = std::fs::read_to_string(format!("tests/golden/{}", golden)).unwrap();
+    assert!(screen == expected, "screen differs from tests/golden/{}:\n{}", golden, screen);
+}
+
+// corax89's opcode test draws OK or a cross next to every opcode it checks
+#[test]
+fn test_opcode_rom() {
+    assert_matches_golden(&run_rom("test_opcode.ch8", QuirkProfile::Vip, 120), "test_opcode.txt");
+}